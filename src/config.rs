@@ -0,0 +1,150 @@
+//! Configuration for the preprocessor, as read from the `[preprocessor.shiftinclude]` table in `book.toml`.
+
+use crate::license::SkipLicense;
+use crate::string::Replacement;
+use anyhow::Context;
+use log::warn;
+use mdbook_preprocessor::{errors::Result, PreprocessorContext};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// Configuration values for the preprocessor.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Whether to also handle `{{#include}}` and `{{#rustdoc_include}}` directives.
+    pub handle_include: bool,
+    /// Additional directive names, each mapped to the default options for that directive.
+    pub aliases: HashMap<String, String>,
+    /// Directories (relative to the book root) to search for included files that are not found
+    /// relative to the including file.
+    pub include_dirs: Vec<PathBuf>,
+    /// Named directories (relative to the book root) that can be referred to as `@name/...`.
+    pub path_aliases: HashMap<String, PathBuf>,
+    /// Values for `${VAR}` placeholders in included paths.
+    pub vars: HashMap<String, String>,
+    /// Whether to prefer translations of included files for the book's language.
+    pub localized_sources: bool,
+    /// Whether to reject included files that are outside the book root and `allowed_dirs`.
+    pub sandbox: bool,
+    /// Directories (relative to the book root) that included files may come from when sandboxed.
+    pub allowed_dirs: Vec<PathBuf>,
+    /// Environment variables that `${VAR}` placeholders may refer to when sandboxed.
+    pub allowed_env: Vec<String>,
+    /// Template for a line to put before each file matched by a glob pattern.
+    pub glob_header: Option<String>,
+    /// Whether to emit a link back to the source repository under each included snippet.
+    pub source_links: bool,
+    /// Revision (branch, tag or commit) to use in links back to the source repository.
+    pub source_link_rev: String,
+    /// Whether to prefix each included line with its line number in the original file.
+    pub line_numbers: bool,
+    /// Whether to include the rest of the file as hidden lines.
+    pub hidden_context: bool,
+    /// Whether to allow `{{#shiftinclude-cmd}}` directives, which run commands.
+    pub allow_commands: bool,
+    /// Number of seconds that an included command may run for before it is killed.
+    pub command_timeout: u64,
+    /// Largest file (in bytes) that can be included, or zero for no limit.
+    pub max_file_size: u64,
+    /// Whether to rewrite relative links in included Markdown to suit the including file.
+    pub rewrite_links: bool,
+    /// When to leave out the license header at the start of included files.
+    pub skip_license: SkipLicense,
+    /// Words (any one of which) a comment block must hold to count as a license header.
+    pub license_keywords: Vec<String>,
+    /// Regex search-and-replaces to apply to all included lines, such as `/secret_\w+/REDACTED/`.
+    pub replace: Vec<Replacement>,
+
+    /// Root directory of the book.
+    #[serde(skip)]
+    pub root: PathBuf,
+    /// Root directory of the source repository that holds the book, if any.
+    #[serde(skip)]
+    pub repository_root: Option<PathBuf>,
+    /// URL of the source repository, from `output.html.git-repository-url`.
+    #[serde(skip)]
+    pub repository_url: Option<String>,
+    /// Language of the book, from `book.language`.
+    #[serde(skip)]
+    pub language: Option<String>,
+    /// Prefixes for hidden lines by language, from `output.html.code.hidelines`.
+    #[serde(skip)]
+    pub hidelines: HashMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            handle_include: false,
+            aliases: HashMap::new(),
+            include_dirs: Vec::new(),
+            path_aliases: HashMap::new(),
+            vars: HashMap::new(),
+            localized_sources: true,
+            sandbox: false,
+            allowed_dirs: Vec::new(),
+            allowed_env: Vec::new(),
+            glob_header: None,
+            source_links: false,
+            source_link_rev: "main".to_string(),
+            line_numbers: false,
+            hidden_context: false,
+            allow_commands: false,
+            command_timeout: 30,
+            max_file_size: 10 * 1024 * 1024,
+            rewrite_links: false,
+            skip_license: SkipLicense::Never,
+            license_keywords: vec![
+                "copyright".to_string(),
+                "license".to_string(),
+                "spdx".to_string(),
+            ],
+            replace: Vec::new(),
+            root: PathBuf::new(),
+            repository_root: None,
+            repository_url: None,
+            language: None,
+            hidelines: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Build the configuration for the preprocessor from the book's context.
+    pub fn from_context(ctx: &PreprocessorContext, name: &str) -> Result<Self> {
+        let key = format!("preprocessor.{name}");
+        let mut cfg = ctx
+            .config
+            .get::<Config>(&key)
+            .with_context(|| format!("Invalid configuration in `{key}`"))?
+            .unwrap_or_default();
+        cfg.root = ctx.root.clone();
+        cfg.repository_root = find_repository_root(&ctx.root);
+        cfg.repository_url = ctx
+            .config
+            .get::<String>("output.html.git-repository-url")
+            .unwrap_or_default();
+        if cfg.source_links && cfg.repository_url.is_none() {
+            warn!("Source links need `output.html.git-repository-url` to be set");
+        }
+        cfg.language = ctx.config.book.language.clone();
+        cfg.hidelines = ctx
+            .config
+            .get("output.html.code.hidelines")
+            .unwrap_or_default()
+            .unwrap_or_default();
+        Ok(cfg)
+    }
+}
+
+/// Find the root of the git working tree that contains `dir`.
+fn find_repository_root(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    dir.ancestors()
+        .find(|d| d.join(".git").exists())
+        .map(Path::to_path_buf)
+}
@@ -0,0 +1,123 @@
+//! Rendering of unified diffs between two sets of included lines.
+
+use crate::string::{shift_lines, Line, Shift};
+use similar::{capture_diff_slices, group_diff_ops, Algorithm, DiffTag};
+
+/// Number of unchanged lines to show around each change.
+const CONTEXT_LINES: usize = 3;
+
+/// Render a unified diff from the `old` lines to the `new` lines, labelling the two sides with the
+/// given names.  The shift is applied to the content of both sides together, so that an `auto`
+/// shift keeps their relative indentation; the header lines are left unshifted.  Hunk headers give
+/// line numbers in the original files.  Returns an empty string if there are no differences.
+pub fn unified_diff(
+    old: &[Line],
+    new: &[Line],
+    old_name: &str,
+    new_name: &str,
+    shift: Shift,
+) -> String {
+    let both = [old, new].concat();
+    let shifted = shift_lines(&both, shift);
+    let (old, new) = shifted.split_at(old.len());
+    let old_text = old.iter().map(|l| l.text.as_str()).collect::<Vec<_>>();
+    let new_text = new.iter().map(|l| l.text.as_str()).collect::<Vec<_>>();
+    let ops = capture_diff_slices(Algorithm::Myers, &old_text, &new_text);
+    let groups = group_diff_ops(ops, CONTEXT_LINES);
+    if groups.is_empty() {
+        return String::new();
+    }
+
+    let mut out = vec![format!("--- {old_name}"), format!("+++ {new_name}")];
+    for group in groups {
+        let (first, last) = (&group[0], &group[group.len() - 1]);
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        out.push(format!(
+            "@@ -{} +{} @@",
+            hunk_range(old, old_range),
+            hunk_range(new, new_range)
+        ));
+        for op in group {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            if matches!(tag, DiffTag::Equal) {
+                out.extend(old_text[old_range].iter().map(|l| format!(" {l}")));
+                continue;
+            }
+            out.extend(old_text[old_range].iter().map(|l| format!("-{l}")));
+            out.extend(new_text[new_range].iter().map(|l| format!("+{l}")));
+        }
+    }
+    out.join("\n")
+}
+
+/// Format the range of original line numbers covered by `range` of `lines`, for a hunk header.
+fn hunk_range(lines: &[Line], range: std::ops::Range<usize>) -> String {
+    let len = range.len();
+    // An empty range is given as the line before it, as for `diff -u`.
+    let start = match lines.get(range.start) {
+        Some(l) if len > 0 => l.num + 1,
+        _ => range
+            .start
+            .checked_sub(1)
+            .and_then(|i| lines.get(i))
+            .map_or(0, |l| l.num + 1),
+    };
+    if len == 1 {
+        start.to_string()
+    } else {
+        format!("{start},{len}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::string::take_lines;
+
+    #[test]
+    fn unified_diff_test() {
+        let old = "fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n";
+        let new = "fn main() {\n    let x = 2;\n    println!(\"{x}\");\n}\n";
+        assert_eq!(
+            unified_diff(
+                &take_lines(old, ..),
+                &take_lines(new, ..),
+                "old.rs",
+                "new.rs",
+                Shift::None
+            ),
+            "--- old.rs\n+++ new.rs\n@@ -1,4 +1,4 @@\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     println!(\"{x}\");\n }"
+        );
+        // Line numbers are those of the original files, and the shift applies to both sides.
+        assert_eq!(
+            unified_diff(
+                &take_lines(old, 1..3),
+                &take_lines(new, 1..2),
+                "a",
+                "b",
+                Shift::Auto
+            ),
+            "--- a\n+++ b\n@@ -2,2 +2 @@\n-let x = 1;\n-println!(\"{x}\");\n+let x = 2;"
+        );
+        assert_eq!(
+            unified_diff(
+                &take_lines(old, ..),
+                &take_lines(old, ..),
+                "a",
+                "b",
+                Shift::None
+            ),
+            ""
+        );
+    }
+
+    #[test]
+    fn hunk_range_test() {
+        let lines = take_lines("a\nb\nc\nd", 1..);
+        assert_eq!(hunk_range(&lines, 0..3), "2,3");
+        assert_eq!(hunk_range(&lines, 1..2), "3");
+        assert_eq!(hunk_range(&lines, 1..1), "2,0");
+        assert_eq!(hunk_range(&lines, 0..0), "0,0");
+    }
+}
@@ -0,0 +1,179 @@
+//! Detection of license headers at the start of included source files.
+
+use crate::string::Line;
+use serde::Deserialize;
+use std::{ops::Range, path::Path};
+
+/// When to leave out the license header at the start of an included file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipLicense {
+    /// Only when a directive asks for it.
+    Never,
+    /// When the whole file is included.
+    FullFile,
+    /// Whenever the included lines start at the first line of the file.
+    Always,
+}
+
+/// Prefixes of line comments, and delimiters of block comments.
+type Syntax = (
+    &'static [&'static str],
+    &'static [(&'static str, &'static str)],
+);
+
+const C_LIKE: Syntax = (&["//"], &[("/*", "*/")]);
+const HASH: Syntax = (&["#"], &[]);
+const DASH: Syntax = (&["--"], &[("--[[", "]]"), ("{-", "-}")]);
+const MARKUP: Syntax = (&[], &[("<!--", "-->")]);
+const PERCENT: Syntax = (&["%"], &[]);
+const SEMICOLON: Syntax = (&[";"], &[]);
+
+/// The comment syntax of the language of `path`, going by its extension (or name).
+fn comment_syntax(path: &Path) -> Option<Syntax> {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let syntax = match ext.as_str() {
+        "rs" | "c" | "h" | "cc" | "cpp" | "cxx" | "hh" | "hpp" | "java" | "js" | "mjs" | "cjs"
+        | "jsx" | "ts" | "tsx" | "go" | "kt" | "kts" | "swift" | "scala" | "cs" | "dart"
+        | "php" | "groovy" | "gradle" | "proto" | "css" | "scss" | "less" | "zig" | "sol" => C_LIKE,
+        "py" | "sh" | "bash" | "zsh" | "fish" | "rb" | "pl" | "pm" | "r" | "toml" | "yaml"
+        | "yml" | "cmake" | "nix" | "tf" | "ps1" | "ex" | "exs" | "jl" | "mk" => HASH,
+        "sql" | "lua" | "hs" | "elm" | "ada" => DASH,
+        "html" | "htm" | "xml" | "svg" | "md" => MARKUP,
+        "tex" | "erl" => PERCENT,
+        "lisp" | "el" | "clj" | "scm" => SEMICOLON,
+        _ => match path.file_name()?.to_str()? {
+            "Makefile" | "Dockerfile" | "CMakeLists.txt" => HASH,
+            _ => return None,
+        },
+    };
+    Some(syntax)
+}
+
+/// Remove the license header from the start of `lines`, if they start at the first line of the
+/// file at `path`.  The header is the first comment block in the file (after any `#!` line), along
+/// with any blank lines after it, and must hold at least one of the `keywords` (ignoring case),
+/// if any are given.
+pub fn skip_license(mut lines: Vec<Line>, path: &Path, keywords: &[String]) -> Vec<Line> {
+    if let Some(header) = license_header(&lines, path, keywords) {
+        lines.drain(header);
+    }
+    lines
+}
+
+/// Find the indices of the lines that make up the license header.
+fn license_header(lines: &[Line], path: &Path, keywords: &[String]) -> Option<Range<usize>> {
+    let (line_prefixes, blocks) = comment_syntax(path)?;
+    let first = lines.first()?;
+    if first.num != 0 || first.hidden {
+        return None;
+    }
+    let start = usize::from(first.text.starts_with("#!"));
+    let text = lines.get(start)?.text.trim_start();
+    let mut end = start;
+    // Block openers come first, as some start with a line prefix (such as Lua's `--[[`).
+    if let Some((open, close)) = blocks.iter().find(|(open, _)| text.starts_with(open)) {
+        // The block runs to the first line with the closing delimiter after the opening one, which
+        // must not be followed by code on the same line.
+        let mut rest = &text[open.len()..];
+        while !rest.contains(close) {
+            end += 1;
+            rest = &lines.get(end)?.text;
+        }
+        let (_, after) = rest.split_once(close)?;
+        if !after.trim().is_empty() {
+            return None;
+        }
+        end += 1;
+    } else if let Some(prefix) = line_prefixes.iter().find(|p| is_line_comment(text, p)) {
+        while lines
+            .get(end)
+            .is_some_and(|l| is_line_comment(l.text.trim_start(), prefix))
+        {
+            end += 1;
+        }
+    } else {
+        return None;
+    }
+
+    let header = lines[start..end]
+        .iter()
+        .map(|l| l.text.to_lowercase())
+        .collect::<Vec<_>>()
+        .join("\n");
+    if !keywords.is_empty() && !keywords.iter().any(|k| header.contains(&k.to_lowercase())) {
+        return None;
+    }
+    while lines
+        .get(end)
+        .is_some_and(|l| !l.hidden && l.text.trim().is_empty())
+    {
+        end += 1;
+    }
+    Some(start..end)
+}
+
+/// Whether `text` is a line comment with the given prefix.  Doc comments (`///` and `//!`) are
+/// about the code that follows, rather than part of a header.
+fn is_line_comment(text: &str, prefix: &str) -> bool {
+    text.starts_with(prefix)
+        && !(prefix == "//" && (text.starts_with("///") || text.starts_with("//!")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::string::{join_lines, take_lines};
+
+    fn skip(s: &str, file: &str, keywords: &[&str]) -> String {
+        let keywords = keywords.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        join_lines(&skip_license(take_lines(s, ..), Path::new(file), &keywords))
+    }
+
+    #[test]
+    fn skip_license_test() {
+        let keywords = ["copyright", "license", "spdx"];
+        let s = "// Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT\n\n//! Crate docs\nfn main() {}";
+        assert_eq!(
+            skip(s, "main.rs", &keywords),
+            "//! Crate docs\nfn main() {}"
+        );
+        // A comment without any of the keywords is left alone.
+        let s = "// Entry point.\nfn main() {}";
+        assert_eq!(skip(s, "main.rs", &keywords), s);
+        assert_eq!(skip(s, "main.rs", &[]), "fn main() {}");
+        assert_eq!(skip(s, "main.txt", &[]), s);
+
+        let s = "/*\n * Licensed under the Apache License, Version 2.0.\n */\npackage main";
+        assert_eq!(skip(s, "main.go", &keywords), "package main");
+        let s = "/* Copyright\nfn main() {}";
+        assert_eq!(skip(s, "main.rs", &keywords), s);
+        let s = "/* Copyright */ int x;\nint y;";
+        assert_eq!(skip(s, "x.c", &keywords), s);
+        let s = "/* Copyright */\nint y;";
+        assert_eq!(skip(s, "x.c", &keywords), "int y;");
+        let s = "--[[\n Copyright 2024\n]]\nlocal x = 1";
+        assert_eq!(skip(s, "init.lua", &keywords), "local x = 1");
+        let s = "-- Copyright 2024\n-- Licensed under MIT\nlocal x = 1";
+        assert_eq!(skip(s, "init.lua", &keywords), "local x = 1");
+
+        // A `#!` line is kept.
+        let s = "#!/bin/sh\n# Copyright 2024\n# Licensed under MIT\n\necho hi";
+        assert_eq!(skip(s, "run.sh", &["SPDX"]), s);
+        assert_eq!(skip(s, "run.sh", &keywords), "#!/bin/sh\necho hi");
+        assert_eq!(skip(s, "Makefile", &keywords), "#!/bin/sh\necho hi");
+
+        let s = "<!-- SPDX-License-Identifier: CC-BY-4.0 -->\n# Title";
+        assert_eq!(skip(s, "README.md", &keywords), "# Title");
+        // Lines that do not start at the first line of the file are left alone.
+        let s = "// Copyright\nfn main() {}";
+        let lines = take_lines(s, 1..);
+        assert_eq!(
+            join_lines(&skip_license(lines, Path::new("a.rs"), &[])),
+            "fn main() {}"
+        );
+    }
+}
@@ -2,9 +2,9 @@
 //!
 //! Based on the links preprocessor in the main mdBook project.
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use clap::{Arg, Command};
-use log::{error, warn};
+use log::{error, info, warn};
 use mdbook_preprocessor::{
     book::{Book, BookItem},
     errors::{Error, Result},
@@ -12,19 +12,47 @@ use mdbook_preprocessor::{
 };
 use regex::{CaptureMatches, Captures, Regex};
 use std::{
-    cmp::Ordering,
-    fs, io,
+    io,
     ops::{Bound, Range, RangeBounds, RangeFrom, RangeFull, RangeTo},
     path::{Path, PathBuf},
     process,
     sync::LazyLock,
+    time::Duration,
 };
 
+mod config;
+mod diff;
+mod license;
+mod markdown;
+mod options;
+mod resolve;
+mod source;
 mod string;
-use string::{take_anchored_lines_with_shift, take_lines_with_shift, Shift};
+mod structured;
+#[cfg(test)]
+mod testing;
+use config::Config;
+use license::SkipLicense;
+use markdown::Fence;
+use options::{split_words, Options};
+use resolve::resolve_path;
+use source::{read_byte_range, read_source, run_command};
+use string::{
+    fill_placeholders, filter_lines, hex_lines, hide_lines, join_lines, line_span, number_lines,
+    replace_lines, shift_lines, take_anchored_lines, take_anchored_lines_with_hidden_context,
+    take_lines, take_lines_with_hidden_context, Line, Shift,
+};
+use structured::select_subtree;
 
 const ESCAPE_CHAR: char = '\\';
 const MAX_LINK_NESTED_DEPTH: usize = 10;
+const OPTIONAL_PREFIX: char = '?';
+const GIT_PREFIX: &str = "git:";
+const DIFF_KEYWORD: &str = "diff";
+const HEX_PREFIX: &str = "hex:";
+const KEY_PREFIX: &str = "key=";
+const HEADING_PREFIX: &str = "heading=";
+const COMMAND_LINK: &str = "shiftinclude-cmd";
 
 fn main() -> Result<(), Error> {
     env_logger::init();
@@ -51,7 +79,7 @@ fn main() -> Result<(), Error> {
         }
     } else {
         let (ctx, book) = mdbook_preprocessor::parse_input(io::stdin())?;
-        let pre = ShiftInclude::new(&ctx);
+        let pre = ShiftInclude::new(&ctx)?;
 
         let processed_book = pre.run(&ctx, book)?;
         serde_json::to_writer(io::stdout(), &processed_book)?;
@@ -61,12 +89,14 @@ fn main() -> Result<(), Error> {
 
 /// A pre-processor for `{{#shiftinclude}}` that acts like `{{#include}}` but allows shifting.
 #[derive(Default)]
-pub struct ShiftInclude;
+pub struct ShiftInclude {
+    cfg: Config,
+}
 
 impl ShiftInclude {
     const NAME: &'static str = "shiftinclude";
 
-    fn new(ctx: &PreprocessorContext) -> Self {
+    fn new(ctx: &PreprocessorContext) -> Result<Self> {
         if ctx.mdbook_version != MDBOOK_VERSION {
             // We should probably use the `semver` crate to check compatibility
             // here...
@@ -77,7 +107,9 @@ impl ShiftInclude {
                 ctx.mdbook_version
             );
         }
-        Self
+        Ok(Self {
+            cfg: Config::from_context(ctx, Self::NAME)?,
+        })
     }
 
     /// Indicate whether a renderer is supported.  This preprocessor can emit MarkDown so should support almost any
@@ -103,7 +135,7 @@ impl Preprocessor for ShiftInclude {
                         .map(|dir| src_dir.join(dir))
                         .expect("All book items have a parent");
 
-                    let content = replace_all(&ch.content, base, chapter_path, 0);
+                    let content = replace_all(&ch.content, base, chapter_path, 0, &self.cfg);
                     ch.content = content;
                 }
             }
@@ -112,7 +144,7 @@ impl Preprocessor for ShiftInclude {
     }
 }
 
-fn replace_all<P1, P2>(s: &str, path: P1, source: P2, depth: usize) -> String
+fn replace_all<P1, P2>(s: &str, path: P1, source: P2, depth: usize, cfg: &Config) -> String
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
@@ -124,17 +156,61 @@ where
     let source = source.as_ref();
     let mut previous_end_index = 0;
     let mut replaced = String::new();
+    // Text to be inserted at a later index of `s`, in order.
+    let mut pending = Vec::<(usize, String)>::new();
 
-    for link in find_links(s) {
-        replaced.push_str(&s[previous_end_index..link.start_index]);
+    for link in find_links(s, cfg) {
+        push_with_pending(
+            &mut replaced,
+            s,
+            previous_end_index..link.start_index,
+            &mut pending,
+        );
 
-        match link.render_with_path(path) {
-            Ok(new_content) => {
+        let fence = markdown::enclosing_fence(s, link.start_index);
+        match link.render_with_path(path, fence.as_ref(), cfg) {
+            Ok(mut rendered) => {
+                let heading_shift = link.link_type.options().and_then(|opts| opts.headings);
+                if let (Some(shift), None) = (heading_shift, &fence) {
+                    // Headings in the included text are placed under the last heading before
+                    // the link.
+                    let level = markdown::headings(&s[..link.start_index])
+                        .last()
+                        .map_or(0, |h| h.level);
+                    rendered.content = markdown::shift_headings(&rendered.content, shift, level);
+                }
+                // Links in text that ends up in a code block are not links.
+                let rewrite_links = fence.is_none()
+                    && link.link_type.options().is_some_and(|opts| {
+                        opts.fence.is_none() && opts.rewrite_links.unwrap_or(cfg.rewrite_links)
+                    });
                 if depth < MAX_LINK_NESTED_DEPTH {
-                    if let Some(rel_path) = link.link_type.relative_path(path) {
-                        replaced.push_str(&replace_all(&new_content, rel_path, source, depth + 1));
+                    if let Some(rel_path) = link.link_type.relative_path(path, cfg) {
+                        let mut content =
+                            replace_all(&rendered.content, &rel_path, source, depth + 1, cfg);
+                        if rewrite_links {
+                            content = markdown::rewrite_links(&content, |url| {
+                                resolve::relative_url(url, &rel_path, path)
+                            });
+                        }
+                        replaced.push_str(&content);
                     } else {
-                        replaced.push_str(&new_content);
+                        replaced.push_str(&rendered.content);
+                    }
+                    if let Some(source_link) = rendered.source_link {
+                        // A link inside a code block would be rendered as code, so put it after
+                        // the end of the block.
+                        match fence {
+                            Some(Fence { end, .. }) => {
+                                let sep = if s[..end].ends_with('\n') {
+                                    "\n"
+                                } else {
+                                    "\n\n"
+                                };
+                                pending.push((end, format!("{sep}{source_link}\n")));
+                            }
+                            None => replaced.push_str(&format!("\n\n{source_link}\n")),
+                        }
                     }
                 } else {
                     error!(
@@ -157,20 +233,203 @@ where
         }
     }
 
-    replaced.push_str(&s[previous_end_index..]);
+    push_with_pending(&mut replaced, s, previous_end_index..s.len(), &mut pending);
+    for (_, text) in pending {
+        replaced.push_str(&text);
+    }
     replaced
 }
 
+/// Push the given range of `s` onto `replaced`, inserting any `pending` text whose index falls
+/// within the range.
+fn push_with_pending(
+    replaced: &mut String,
+    s: &str,
+    range: Range<usize>,
+    pending: &mut Vec<(usize, String)>,
+) {
+    let mut start = range.start;
+    while let Some((idx, _)) = pending.first() {
+        if *idx > range.end {
+            break;
+        }
+        let (idx, text) = pending.remove(0);
+        let idx = idx.max(start);
+        replaced.push_str(&s[start..idx]);
+        replaced.push_str(&text);
+        start = idx;
+    }
+    replaced.push_str(&s[start..range.end]);
+}
+
+/// Build a Markdown link to the given lines of `target` in the source repository, if configured.
+/// The link is to the revision given in the options, or to the configured revision if none is
+/// given.
+fn source_link(
+    cfg: &Config,
+    opts: &Options,
+    target: &Path,
+    lines: Option<Range<usize>>,
+) -> Option<String> {
+    if !opts.source_link.unwrap_or(cfg.source_links) {
+        return None;
+    }
+    let url = cfg.repository_url.as_ref()?;
+    let repo_root = cfg.repository_root.as_ref()?;
+    let target = target.canonicalize().ok()?;
+    let Ok(rel_path) = target.strip_prefix(repo_root) else {
+        warn!(
+            "Cannot link to {} as it is outside the repository",
+            target.display()
+        );
+        return None;
+    };
+    let rel_path = rel_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let fragment = match lines {
+        Some(r) if r.len() == 1 => format!("#L{}", r.end),
+        Some(r) => format!("#L{}-L{}", r.start + 1, r.end),
+        None => String::new(),
+    };
+    Some(format!(
+        "[{rel_path}{fragment}]({}/blob/{}/{rel_path}{fragment})",
+        url.trim_end_matches('/'),
+        opts.rev.as_deref().unwrap_or(&cfg.source_link_rev),
+    ))
+}
+
+/// Take the lines selected by a link from the text of the included file.
+fn take_included_lines(
+    s: &str,
+    range_or_anchor: &RangeOrAnchor,
+    opts: &Options,
+    cfg: &Config,
+) -> Vec<Line> {
+    let hidden_context = opts.hidden_context.unwrap_or(cfg.hidden_context);
+    match (range_or_anchor, hidden_context) {
+        (RangeOrAnchor::Range(range), false) => take_lines(s, range.clone()),
+        (RangeOrAnchor::Range(range), true) => take_lines_with_hidden_context(s, range.clone()),
+        (RangeOrAnchor::Anchor(anchor), false) => take_anchored_lines(s, anchor),
+        (RangeOrAnchor::Anchor(anchor), true) => take_anchored_lines_with_hidden_context(s, anchor),
+        (RangeOrAnchor::Heading(name), hidden_context) => {
+            let Some(section) = markdown::find_section(s, name) else {
+                warn!("No heading matches \"{name}\"");
+                return Vec::new();
+            };
+            let start = if opts.include_heading.unwrap_or(true) {
+                section.heading.line
+            } else {
+                section.body.start
+            };
+            let range = start..section.body.end;
+            if hidden_context {
+                take_lines_with_hidden_context(s, range)
+            } else {
+                take_lines(s, range)
+            }
+        }
+        (RangeOrAnchor::Bytes(_), _) => unreachable!("hex dumps are taken from raw bytes"),
+        (RangeOrAnchor::Key(_), _) => unreachable!("keys are selected from the parsed file"),
+    }
+}
+
+/// Format the lines selected by a link into the text that replaces the link.
+fn format_lines(
+    lines: &[Line],
+    shift: Shift,
+    opts: &Options,
+    fence: Option<&Fence>,
+    cfg: &Config,
+) -> String {
+    let mut shifted = shift_lines(lines, shift);
+    if opts.line_numbers.unwrap_or(cfg.line_numbers) {
+        shifted = number_lines(&shifted);
+    }
+    if opts.hidden_context.unwrap_or(cfg.hidden_context) {
+        // Hidden lines are marked according to the code block they end up in.
+        let own_fence = opts.fence.as_ref().map(|info| Fence {
+            info: info.clone(),
+            end: 0,
+        });
+        let fence = own_fence.as_ref().or(fence);
+        shifted = hide_lines(&shifted, hidden_line_prefix(fence, cfg));
+    }
+    join_lines(&shifted)
+}
+
+/// Wrap the text that replaces a link in a fenced code block, if requested.
+fn wrap_in_fence(content: String, opts: &Options) -> String {
+    match &opts.fence {
+        Some(info) => markdown::fenced(&content, info),
+        None => content,
+    }
+}
+
+/// Determine the prefix that marks a line as hidden in the code block holding a link.
+fn hidden_line_prefix<'a>(fence: Option<&'a Fence>, cfg: &'a Config) -> &'a str {
+    fence
+        .and_then(|f| {
+            f.hidelines().or_else(|| {
+                f.lang()
+                    .and_then(|lang| cfg.hidelines.get(lang).map(String::as_str))
+            })
+        })
+        .unwrap_or("# ")
+}
+
+/// The result of rendering a link.
+struct Rendered {
+    /// Content to replace the link with.
+    content: String,
+    /// Optional link back to the source of the content.
+    source_link: Option<String>,
+}
+
+impl From<String> for Rendered {
+    fn from(content: String) -> Self {
+        Self {
+            content,
+            source_link: None,
+        }
+    }
+}
+
 #[derive(PartialEq, Debug, Clone)]
 enum LinkType {
     Escaped,
-    Include(PathBuf, RangeOrAnchor, Shift),
+    Include(PathBuf, RangeOrAnchor, Shift, Options),
+    /// A diff from the first included text to the second, each of which is an `Include`.
+    Diff(Box<LinkType>, Box<LinkType>),
+    /// The output of a command, run with the system shell.
+    Command(String, RangeOrAnchor, Shift, Options),
 }
 
 #[derive(PartialEq, Debug, Clone)]
 enum RangeOrAnchor {
     Range(LineRange),
     Anchor(String),
+    /// A range of bytes, to be shown as a hex dump.
+    Bytes(LineRange),
+    /// The key of a subtree of a structured (JSON, TOML or YAML) file.
+    Key(String),
+    /// The text or identifier of a heading in a Markdown file.
+    Heading(String),
+}
+
+impl RangeOrAnchor {
+    /// Whether the whole of the text is selected.
+    fn is_full(&self) -> bool {
+        match self {
+            RangeOrAnchor::Range(range) => {
+                matches!(range.start_bound(), Bound::Unbounded | Bound::Included(0))
+                    && range.end_bound() == Bound::Unbounded
+            }
+            _ => false,
+        }
+    }
 }
 
 // A range of lines specified with some include directive.
@@ -228,23 +487,38 @@ impl From<RangeFull> for LineRange {
 }
 
 impl LinkType {
-    fn relative_path<P: AsRef<Path>>(self, base: P) -> Option<PathBuf> {
+    /// The options given for the link, if it has any.
+    fn options(&self) -> Option<&Options> {
+        match self {
+            LinkType::Include(_, _, _, opts) | LinkType::Command(_, _, _, opts) => Some(opts),
+            LinkType::Escaped | LinkType::Diff(_, _) => None,
+        }
+    }
+
+    fn relative_path<P: AsRef<Path>>(self, base: P, cfg: &Config) -> Option<PathBuf> {
         let base = base.as_ref();
         match self {
-            LinkType::Escaped => None,
-            LinkType::Include(p, _, _) => Some(return_relative_path(base, &p)),
+            LinkType::Escaped | LinkType::Diff(_, _) | LinkType::Command(_, _, _, _) => None,
+            LinkType::Include(p, _, _, _) => resolve_path(base, &p, cfg)
+                .ok()
+                .map(|target| return_relative_path(&target)),
         }
     }
 }
-fn return_relative_path<P: AsRef<Path>>(base: P, relative: P) -> PathBuf {
-    base.as_ref()
-        .join(relative)
+fn return_relative_path(target: &Path) -> PathBuf {
+    target
         .parent()
         .expect("Included file should not be /")
         .to_path_buf()
 }
 
 fn parse_range_or_anchor(parts: Option<&str>) -> RangeOrAnchor {
+    if let Some(key) = parts.and_then(|p| p.strip_prefix(KEY_PREFIX)) {
+        return RangeOrAnchor::Key(key.to_string());
+    }
+    if let Some(heading) = parts.and_then(|p| p.strip_prefix(HEADING_PREFIX)) {
+        return RangeOrAnchor::Heading(heading.to_string());
+    }
     let mut parts = parts.unwrap_or("").splitn(3, ':').fuse();
 
     let next_element = parts.next();
@@ -274,28 +548,118 @@ fn parse_range_or_anchor(parts: Option<&str>) -> RangeOrAnchor {
     }
 }
 
-fn parse_shift_include_path(params: &str) -> LinkType {
-    let mut params = params.splitn(2, ':');
-    let param0 = params.next().unwrap();
-    let shift = if param0 == "auto" {
-        Shift::Auto
-    } else {
-        let shift: isize = param0.parse().unwrap_or_else(|e| {
-            log::error!("failed to parse shift amount: {e:?}");
-            0
-        });
-        match shift.cmp(&0) {
-            Ordering::Greater => Shift::Right(shift as usize),
-            Ordering::Equal => Shift::None,
-            Ordering::Less => Shift::Left(-shift as usize),
+/// Parse a range of (0-based) byte offsets, as `start:end`, `start:`, `:end` or `start` (which runs
+/// to the end of the file).
+fn parse_byte_range(parts: Option<&str>) -> LineRange {
+    let (start, end) = match parts.unwrap_or("").split_once(':') {
+        Some((start, end)) => (start, end),
+        None => (parts.unwrap_or(""), ""),
+    };
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .inspect_err(|e| {
+                if !s.is_empty() {
+                    log::error!("failed to parse byte offset {s:?}: {e:?}");
+                }
+            })
+            .ok()
+    };
+    match (parse(start), parse(end)) {
+        (Some(start), Some(end)) => LineRange::from(start..end),
+        (Some(start), None) => LineRange::from(start..),
+        (None, Some(end)) => LineRange::from(..end),
+        (None, None) => LineRange::from(RangeFull),
+    }
+}
+
+/// Complete the options of one of mdBook's own `include` and `rustdoc_include` directives, which
+/// follow mdBook's semantics: the defaults from the configuration are all turned off, so that only
+/// the options written in the directive change what it renders.
+fn mdbook_options(mut opts: Options) -> Options {
+    opts.line_numbers.get_or_insert(false);
+    opts.hidden_context.get_or_insert(false);
+    opts.rewrite_links.get_or_insert(false);
+    opts.skip_license.get_or_insert(false);
+    opts.config_replace.get_or_insert(false);
+    opts.source_link.get_or_insert(false);
+    opts
+}
+
+fn parse_include_path(path: &str, shift: Shift, mut opts: Options) -> LinkType {
+    let path = match path.strip_prefix(OPTIONAL_PREFIX) {
+        Some(rest) => {
+            opts.optional = Some(true);
+            rest
         }
+        None => path,
+    };
+    let path = match path
+        .strip_prefix(GIT_PREFIX)
+        .and_then(|p| p.split_once(':'))
+    {
+        Some((rev, rest)) => {
+            opts.rev = Some(rev.to_string());
+            rest
+        }
+        None => path,
+    };
+    let (path, hex) = match path.strip_prefix(HEX_PREFIX) {
+        Some(rest) => (rest, true),
+        None => (path, false),
     };
-    let mut parts = params.next().unwrap().splitn(2, ':');
+    let mut parts = path.splitn(2, ':');
 
     let path = parts.next().unwrap().into();
-    let range_or_anchor = parse_range_or_anchor(parts.next());
+    let range_or_anchor = if hex {
+        RangeOrAnchor::Bytes(parse_byte_range(parts.next()))
+    } else {
+        parse_range_or_anchor(parts.next())
+    };
+
+    LinkType::Include(path, range_or_anchor, shift, opts)
+}
+
+/// Parse the text of a command directive, which is a shift, then any options (including `lines=`
+/// and `anchor=` to select from the output), then the command to run.
+fn parse_command(params: &str) -> Option<LinkType> {
+    let (shift, mut rest) = params.split_once(':')?;
+    let shift = shift.parse().unwrap_or_else(|e| {
+        log::error!("failed to parse shift amount: {e:?}");
+        Shift::None
+    });
+    let mut opts = Options::default();
+    let mut range_or_anchor = RangeOrAnchor::Range(LineRange::from(RangeFull));
+    loop {
+        rest = rest.trim_start();
+        let word = rest.split(char::is_whitespace).next().unwrap_or_default();
+        match word.split_once('=') {
+            Some(("lines", lines)) => range_or_anchor = parse_range_or_anchor(Some(lines)),
+            Some(("anchor", anchor)) => range_or_anchor = RangeOrAnchor::Anchor(anchor.to_string()),
+            Some((key, _)) if Options::is_option(key) => opts.apply(&[word]),
+            _ => break,
+        }
+        rest = &rest[word.len()..];
+    }
+    let cmd = rest.trim_end();
+    if cmd.is_empty() {
+        return None;
+    }
+    Some(LinkType::Command(
+        cmd.to_string(),
+        range_or_anchor,
+        shift,
+        opts,
+    ))
+}
 
-    LinkType::Include(path, range_or_anchor, shift)
+/// Parse a `<shift>:<path>` parameter, or return `None` if the shift is left out.
+fn parse_shift_include_path(params: &str, opts: Options) -> Option<LinkType> {
+    let (param0, path) = params.split_once(':')?;
+    let shift = param0.parse().unwrap_or_else(|e| {
+        log::error!("failed to parse shift amount: {e:?}");
+        Shift::None
+    });
+    Some(parse_include_path(path, shift, opts))
 }
 
 #[derive(PartialEq, Debug, Clone)]
@@ -307,14 +671,38 @@ struct Link<'a> {
 }
 
 impl<'a> Link<'a> {
-    fn from_capture(cap: Captures<'a>) -> Option<Link<'a>> {
+    fn from_capture(cap: Captures<'a>, cfg: &Config) -> Option<Link<'a>> {
         let link_type = match (cap.get(0), cap.get(1), cap.get(2)) {
+            (_, Some(typ), Some(rest)) if typ.as_str() == COMMAND_LINK => {
+                parse_command(rest.as_str())
+            }
             (_, Some(typ), Some(rest)) => {
-                let mut path_props = rest.as_str().split_whitespace();
-                let file_arg = path_props.next();
+                let words = split_words(rest.as_str());
 
-                match (typ.as_str(), file_arg) {
-                    ("shiftinclude", Some(pth)) => Some(parse_shift_include_path(pth)),
+                match (typ.as_str(), words.split_first()) {
+                    ("shiftinclude", Some((kw, rest))) if kw == DIFF_KEYWORD && rest.len() >= 2 => {
+                        let props = Options::parse(&rest[2..]);
+                        let old = parse_shift_include_path(&rest[0], props.clone())?;
+                        let new = parse_include_path(&rest[1], Shift::None, props);
+                        Some(LinkType::Diff(Box::new(old), Box::new(new)))
+                    }
+                    ("shiftinclude", Some((pth, props))) => {
+                        parse_shift_include_path(pth, Options::parse(props))
+                    }
+                    ("include", Some((pth, props))) if cfg.handle_include => {
+                        let opts = mdbook_options(Options::parse(props));
+                        Some(parse_include_path(pth, Shift::None, opts))
+                    }
+                    ("rustdoc_include", Some((pth, props))) if cfg.handle_include => {
+                        let mut opts = Options::parse(props);
+                        opts.hidden_context.get_or_insert(true);
+                        Some(parse_include_path(pth, Shift::None, mdbook_options(opts)))
+                    }
+                    (name, Some((pth, props))) if cfg.aliases.contains_key(name) => {
+                        let mut opts = Options::parse(&split_words(&cfg.aliases[name]));
+                        opts.apply(props);
+                        Some(parse_include_path(pth, Shift::None, opts))
+                    }
                     _ => None,
                 }
             }
@@ -334,42 +722,341 @@ impl<'a> Link<'a> {
         })
     }
 
-    fn render_with_path<P: AsRef<Path>>(&self, base: P) -> Result<String> {
+    fn render_with_path<P: AsRef<Path>>(
+        &self,
+        base: P,
+        fence: Option<&Fence>,
+        cfg: &Config,
+    ) -> Result<Rendered> {
         let base = base.as_ref();
         match self.link_type {
             // omit the escape char
-            LinkType::Escaped => Ok(self.link_text[1..].to_owned()),
-            LinkType::Include(ref pat, ref range_or_anchor, shift) => {
-                let target = base.join(pat);
-
-                fs::read_to_string(&target)
-                    .map(|s| match range_or_anchor {
-                        RangeOrAnchor::Range(range) => {
-                            take_lines_with_shift(&s, range.clone(), shift)
-                        }
-                        RangeOrAnchor::Anchor(anchor) => {
-                            take_anchored_lines_with_shift(&s, anchor, shift)
-                        }
-                    })
-                    .with_context(|| {
-                        format!(
-                            "Could not read file for link {} ({})",
-                            self.link_text,
-                            target.display(),
-                        )
-                    })
+            LinkType::Escaped => Ok(self.link_text[1..].to_owned().into()),
+            LinkType::Include(ref pat, ref range_or_anchor, shift, ref opts) => {
+                let shift = opts.shift.unwrap_or(shift);
+                if resolve::is_glob(base, pat, cfg) {
+                    return self.render_glob(base, pat, range_or_anchor, shift, opts, fence, cfg);
+                }
+                if let RangeOrAnchor::Bytes(range) = range_or_anchor {
+                    let read = |target: &Path| read_byte_range(target, opts, cfg, range.clone());
+                    let Some((target, (start, bytes))) =
+                        self.read_target(base, pat, opts, cfg, read)?
+                    else {
+                        return Ok(opts.default.clone().unwrap_or_default().into());
+                    };
+                    let lines = hex_lines(&bytes, start);
+                    return Ok(Rendered {
+                        content: wrap_in_fence(join_lines(&shift_lines(&lines, shift)), opts),
+                        source_link: source_link(cfg, opts, &target, None),
+                    });
+                }
+                let read = |target: &Path| read_source(target, opts, cfg);
+                let Some((target, s)) = self.read_target(base, pat, opts, cfg, read)? else {
+                    return Ok(opts.default.clone().unwrap_or_default().into());
+                };
+                let (lines, verbatim) = match range_or_anchor {
+                    RangeOrAnchor::Key(key) => {
+                        let subtree = self.select_key(&s, &target, key)?;
+                        (subtree.lines, subtree.verbatim)
+                    }
+                    _ => (take_included_lines(&s, range_or_anchor, opts, cfg), true),
+                };
+                let lines = self.transform_lines(lines, Some(&target), range_or_anchor, opts, cfg);
+                // The link covers the lines that are left after any license header is skipped.
+                let span = if verbatim { line_span(&lines) } else { None };
+                // Re-serialized lines have no line numbers in the file.
+                let unnumbered;
+                let opts = if !verbatim && opts.line_numbers.unwrap_or(cfg.line_numbers) {
+                    warn!(
+                        "Cannot number the lines of a re-serialized subtree, in link {}",
+                        self.link_text
+                    );
+                    unnumbered = Options {
+                        line_numbers: Some(false),
+                        ..opts.clone()
+                    };
+                    &unnumbered
+                } else {
+                    opts
+                };
+                Ok(Rendered {
+                    content: wrap_in_fence(format_lines(&lines, shift, opts, fence, cfg), opts),
+                    source_link: source_link(cfg, opts, &target, span),
+                })
+            }
+            LinkType::Diff(ref old, ref new) => self.render_diff(base, old, new, cfg),
+            LinkType::Command(ref cmd, ref range_or_anchor, shift, ref opts) => {
+                if !cfg.allow_commands {
+                    bail!(
+                        "Commands are not allowed (set `allow-commands = true` to allow them), \
+                         in link {}",
+                        self.link_text
+                    );
+                }
+                let shift = opts.shift.unwrap_or(shift);
+                let dir =
+                    resolve::resolve_command_dir(opts.cwd.as_deref(), cfg).with_context(|| {
+                        format!("Could not resolve directory for link {}", self.link_text)
+                    })?;
+                let timeout = Duration::from_secs(opts.timeout.unwrap_or(cfg.command_timeout));
+                let max_size = opts.max_size.unwrap_or(cfg.max_file_size);
+                let s = run_command(cmd, &dir, timeout, max_size, opts).with_context(|| {
+                    format!("Could not run command for link {}", self.link_text)
+                })?;
+                let lines = take_included_lines(&s, range_or_anchor, opts, cfg);
+                let lines = self.transform_lines(lines, None, range_or_anchor, opts, cfg);
+                Ok(wrap_in_fence(format_lines(&lines, shift, opts, fence, cfg), opts).into())
             }
         }
     }
 }
 
-struct LinkIter<'a>(CaptureMatches<'a, 'a>);
+impl Link<'_> {
+    /// Resolve the file at `pat` and read it with `read`, returning the path to it and its
+    /// contents, or `None` if it is missing but optional.
+    fn read_target<T>(
+        &self,
+        base: &Path,
+        pat: &Path,
+        opts: &Options,
+        cfg: &Config,
+        read: impl FnOnce(&Path) -> io::Result<T>,
+    ) -> Result<Option<(PathBuf, T)>> {
+        let target = resolve_path(base, pat, cfg)
+            .with_context(|| format!("Could not resolve path for link {}", self.link_text))?;
+        match read(&target) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && opts.is_optional() => {
+                info!(
+                    "Optional file {} not found for link {}",
+                    target.display(),
+                    self.link_text
+                );
+                Ok(None)
+            }
+            result => {
+                let s = result.with_context(|| {
+                    format!(
+                        "Could not read file for link {} ({})",
+                        self.link_text,
+                        target.display(),
+                    )
+                })?;
+                Ok(Some((target, s)))
+            }
+        }
+    }
+
+    /// Transform the lines selected from `target` (if the text comes from a file) before they are
+    /// shifted: leave out any license header, filter them, fill in placeholders, then apply the
+    /// configured replacements and those given in the link.
+    fn transform_lines(
+        &self,
+        lines: Vec<Line>,
+        target: Option<&Path>,
+        range_or_anchor: &RangeOrAnchor,
+        opts: &Options,
+        cfg: &Config,
+    ) -> Vec<Line> {
+        let skip_license = opts.skip_license.unwrap_or(match cfg.skip_license {
+            SkipLicense::Never => false,
+            SkipLicense::FullFile => range_or_anchor.is_full(),
+            SkipLicense::Always => true,
+        });
+        let lines = match target {
+            Some(target) if skip_license => {
+                let keywords = match &opts.license_keyword {
+                    Some(keyword) => std::slice::from_ref(keyword),
+                    None => &cfg.license_keywords,
+                };
+                license::skip_license(lines, target, keywords)
+            }
+            _ => lines,
+        };
+        let lines = if opts.hide.is_empty() && opts.keep.is_empty() {
+            lines
+        } else {
+            // With hidden context, filtered lines stay in the code as hidden lines.
+            let as_hidden = opts.hidden_context.unwrap_or(cfg.hidden_context);
+            filter_lines(&lines, &opts.hide, &opts.keep, as_hidden)
+        };
+        let lines = self.fill_params(lines, opts);
+        let configured = if opts.config_replace.unwrap_or(true) {
+            &cfg.replace[..]
+        } else {
+            &[]
+        };
+        if configured.is_empty() && opts.replace.is_empty() {
+            return lines;
+        }
+        replace_lines(&lines, configured.iter().chain(&opts.replace))
+    }
+
+    /// Fill in `{{name}}` placeholders in the included lines with the parameters given in the
+    /// link, if there are any, and report any placeholders or parameters that do not match up.
+    fn fill_params(&self, lines: Vec<Line>, opts: &Options) -> Vec<Line> {
+        if opts.params.is_empty() {
+            return lines;
+        }
+        let filled = fill_placeholders(&lines, &opts.params);
+        for name in &filled.missing {
+            warn!(
+                "No value for placeholder {{{{{name}}}}} in link {}",
+                self.link_text
+            );
+        }
+        for name in &filled.unused {
+            warn!("Unused parameter \"{name}\" in link {}", self.link_text);
+        }
+        filled.lines
+    }
 
-impl<'a> Iterator for LinkIter<'a> {
+    /// Select the subtree with the given key from the text of `target`.
+    fn select_key(&self, s: &str, target: &Path, key: &str) -> Result<structured::Subtree> {
+        select_subtree(s, target, key).with_context(|| {
+            format!(
+                "Could not select key {key:?} for link {} ({})",
+                self.link_text,
+                target.display()
+            )
+        })
+    }
+
+    /// Render a link that gives a diff between two included texts.  A missing optional file is
+    /// treated as empty, so that the diff shows the whole of the other file as added or removed.
+    fn render_diff(
+        &self,
+        base: &Path,
+        old: &LinkType,
+        new: &LinkType,
+        cfg: &Config,
+    ) -> Result<Rendered> {
+        let mut sides = Vec::new();
+        for side in [old, new] {
+            let LinkType::Include(pat, range_or_anchor, _, opts) = side else {
+                unreachable!("diffs are between includes");
+            };
+            if resolve::is_glob(base, pat, cfg) {
+                bail!(
+                    "Glob patterns cannot be used in a diff, in link {}",
+                    self.link_text
+                );
+            }
+            // Hidden lines have no place in a diff.
+            let visible = Options {
+                hidden_context: Some(false),
+                ..opts.clone()
+            };
+            let lines = if let RangeOrAnchor::Bytes(range) = range_or_anchor {
+                let read = |target: &Path| read_byte_range(target, opts, cfg, range.clone());
+                match self.read_target(base, pat, opts, cfg, read)? {
+                    Some((_, (start, bytes))) => hex_lines(&bytes, start),
+                    None => Vec::new(),
+                }
+            } else {
+                let read = |target: &Path| read_source(target, opts, cfg);
+                match self.read_target(base, pat, opts, cfg, read)? {
+                    Some((target, s)) => {
+                        let lines = match range_or_anchor {
+                            RangeOrAnchor::Range(range) => take_lines(&s, range.clone()),
+                            RangeOrAnchor::Anchor(anchor) => take_anchored_lines(&s, anchor),
+                            RangeOrAnchor::Key(key) => self.select_key(&s, &target, key)?.lines,
+                            RangeOrAnchor::Heading(_) => {
+                                take_included_lines(&s, range_or_anchor, &visible, cfg)
+                            }
+                            RangeOrAnchor::Bytes(_) => unreachable!(),
+                        };
+                        self.transform_lines(lines, Some(&target), range_or_anchor, &visible, cfg)
+                    }
+                    None => Vec::new(),
+                }
+            };
+            let name = match &opts.rev {
+                Some(rev) => format!("{rev}:{}", pat.display()),
+                None => pat.display().to_string(),
+            };
+            sides.push((lines, name));
+        }
+        let LinkType::Include(_, _, shift, opts) = old else {
+            unreachable!("diffs are between includes");
+        };
+        let shift = opts.shift.unwrap_or(*shift);
+        let content = diff::unified_diff(&sides[0].0, &sides[1].0, &sides[0].1, &sides[1].1, shift);
+        if content.is_empty() {
+            warn!("No differences for link {}", self.link_text);
+        }
+        // A bare `fence` option gives a block that is highlighted as a diff.
+        let opts = match opts.fence.as_deref() {
+            Some("") => Options {
+                fence: Some(DIFF_KEYWORD.to_string()),
+                ..opts.clone()
+            },
+            _ => opts.clone(),
+        };
+        Ok(wrap_in_fence(content, &opts).into())
+    }
+
+    /// Render a link whose path is a glob pattern, by concatenating all of the matching files.
+    #[allow(clippy::too_many_arguments)]
+    fn render_glob(
+        &self,
+        base: &Path,
+        pattern: &Path,
+        range_or_anchor: &RangeOrAnchor,
+        shift: Shift,
+        opts: &Options,
+        fence: Option<&Fence>,
+        cfg: &Config,
+    ) -> Result<Rendered> {
+        if opts.rev.is_some() {
+            bail!(
+                "Glob patterns cannot be used with a git revision, in link {}",
+                self.link_text
+            );
+        }
+        if let RangeOrAnchor::Bytes(_) | RangeOrAnchor::Key(_) = range_or_anchor {
+            bail!(
+                "Glob patterns cannot be used with a hex dump or key, in link {}",
+                self.link_text
+            );
+        }
+        let matches = resolve::resolve_glob(base, pattern, cfg).with_context(|| {
+            format!("Could not resolve glob pattern for link {}", self.link_text)
+        })?;
+        if matches.is_empty() {
+            if opts.is_optional() {
+                info!("No files match optional link {}", self.link_text);
+                return Ok(opts.default.clone().unwrap_or_default().into());
+            }
+            bail!("No files match link {}", self.link_text);
+        }
+        let header = opts.header.as_ref().or(cfg.glob_header.as_ref());
+        let mut parts = Vec::new();
+        for (target, path) in matches {
+            let s = read_source(&target, opts, cfg).with_context(|| {
+                format!(
+                    "Could not read file for link {} ({})",
+                    self.link_text,
+                    target.display(),
+                )
+            })?;
+            if let Some(header) = header {
+                parts.push(header.replace("{path}", &path));
+            }
+            let lines = take_included_lines(&s, range_or_anchor, opts, cfg);
+            let lines = self.transform_lines(lines, Some(&target), range_or_anchor, opts, cfg);
+            parts.push(format_lines(&lines, shift, opts, fence, cfg));
+        }
+        Ok(wrap_in_fence(parts.join("\n"), opts).into())
+    }
+}
+
+struct LinkIter<'a, 'c>(CaptureMatches<'a, 'a>, &'c Config);
+
+impl<'a> Iterator for LinkIter<'a, '_> {
     type Item = Link<'a>;
     fn next(&mut self) -> Option<Link<'a>> {
         for cap in &mut self.0 {
-            if let Some(inc) = Link::from_capture(cap) {
+            if let Some(inc) = Link::from_capture(cap, self.1) {
                 return Some(inc);
             }
         }
@@ -378,28 +1065,31 @@ impl<'a> Iterator for LinkIter<'a> {
 }
 
 // lazily compute following regex
-// r"\\\{\{#.*\}\}|\{\{#([a-zA-Z0-9]+)\s*([^}]+)\}\}")?;
+// r"\\\{\{#.*\}\}|\{\{#([a-zA-Z0-9_-]+)\s*((?:\{[^{}]*\}|[^}])+)\}\}")?;
 static LINK_RE: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(
         r"(?x)              # insignificant whitespace mode
         \\\{\{\#.*\}\}      # match escaped link
         |                   # or
         \{\{\s*             # link opening parens and whitespace
-        \#([a-zA-Z0-9_]+)   # link type
+        \#([a-zA-Z0-9_-]+)  # link type
         \s+                 # separating whitespace
-        ([^}]+)             # link target path and space separated properties
+        ((?:\{[^{}]*\}       # link target path and space separated properties,
+        |[^}])+)            # which may include `{...}` placeholders
         \}\}                # link closing parens",
     )
     .unwrap()
 });
 
-fn find_links(contents: &str) -> LinkIter<'_> {
-    LinkIter(LINK_RE.captures_iter(contents))
+fn find_links<'a, 'c>(contents: &'a str, cfg: &'c Config) -> LinkIter<'a, 'c> {
+    LinkIter(LINK_RE.captures_iter(contents), cfg)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
+    use testing::{fixture, git};
 
     #[test]
     fn test_replace_all_escaped() {
@@ -413,41 +1103,114 @@ mod tests {
         ```hbs
         {{#shiftinclude 0:file.rs}} << an escaped link!
         ```";
-        assert_eq!(replace_all(start, "", "", 0), end);
+        assert_eq!(replace_all(start, "", "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_source_link() {
+        let tmp = fixture(&[
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/lib.rs", "// Copyright 2024\n\npub fn f() {}\n"),
+        ]);
+        let dir = tmp.path();
+        let cfg = Config {
+            source_links: true,
+            repository_root: Some(dir.canonicalize().unwrap()),
+            repository_url: Some("https://github.com/u/r/".to_string()),
+            ..Config::default()
+        };
+        let target = dir.join("src/main.rs");
+        assert_eq!(
+            source_link(&cfg, &Options::default(), &target, Some(9..20)).unwrap(),
+            "[src/main.rs#L10-L20](https://github.com/u/r/blob/main/src/main.rs#L10-L20)"
+        );
+        assert_eq!(
+            source_link(&cfg, &Options::default(), &target, Some(9..10)).unwrap(),
+            "[src/main.rs#L10](https://github.com/u/r/blob/main/src/main.rs#L10)"
+        );
+        assert_eq!(
+            source_link(&cfg, &Options::default(), &target, None).unwrap(),
+            "[src/main.rs](https://github.com/u/r/blob/main/src/main.rs)"
+        );
+        assert_eq!(
+            source_link(&cfg, &Options::parse(&["rev=v1.2.0"]), &target, Some(9..10)).unwrap(),
+            "[src/main.rs#L10](https://github.com/u/r/blob/v1.2.0/src/main.rs#L10)"
+        );
+        assert_eq!(
+            source_link(&Config::default(), &Options::default(), &target, None),
+            None
+        );
+        let opts = Options::parse(&["source-link=false"]);
+        assert_eq!(source_link(&cfg, &opts, &target, None), None);
+
+        let start = "```rust\n{{#shiftinclude 0:src/main.rs}}\n```\nAfter\n";
+        let end = "```rust\nfn main() {}\n```\n\n[src/main.rs#L1](https://github.com/u/r/blob/main/src/main.rs#L1)\nAfter\n";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        // A skipped license header is not covered by the link.
+        let start = "{{#shiftinclude 0:src/lib.rs skip-license}}";
+        let end =
+            "pub fn f() {}\n\n[src/lib.rs#L3](https://github.com/u/r/blob/main/src/lib.rs#L3)\n";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+    }
+
+    #[test]
+    fn test_hidden_line_prefix() {
+        let fence = |info: &str| Fence {
+            info: info.to_string(),
+            end: 0,
+        };
+        let cfg = Config {
+            hidelines: [("python".to_string(), "~".to_string())].into(),
+            ..Config::default()
+        };
+        assert_eq!(hidden_line_prefix(None, &cfg), "# ");
+        assert_eq!(hidden_line_prefix(Some(&fence("rust")), &cfg), "# ");
+        assert_eq!(hidden_line_prefix(Some(&fence("python")), &cfg), "~");
+        assert_eq!(
+            hidden_line_prefix(Some(&fence("python,hidelines=!!")), &cfg),
+            "!!"
+        );
     }
 
     #[test]
     fn test_find_links_no_link() {
         let s = "Some random text without link...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
     }
 
     #[test]
     fn test_find_links_partial_link() {
         let s = "Some random text with {{#playground...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
         let s = "Some random text with {{#include...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
         let s = "Some random text with \\{{#include...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
     }
 
     #[test]
     fn test_find_links_empty_link() {
         let s = "Some random text with {{#playground}} and {{#playground   }} {{}} {{#}}...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
+    }
+
+    #[test]
+    fn test_find_links_missing_shift() {
+        let s =
+            "{{#shiftinclude file.rs}} {{#shiftinclude diff old.rs new.rs}} {{#shiftinclude diff}}";
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
     }
 
     #[test]
     fn test_find_links_unknown_link_type() {
         let s = "Some random text with {{#playgroundz ar.rs}} and {{#incn}} {{baz}} {{#bar}}...";
-        assert!(find_links(s).collect::<Vec<_>>() == vec![]);
+        assert!(find_links(s, &Config::default()).collect::<Vec<_>>() == vec![]);
     }
 
     #[test]
     fn test_find_links_with_range() {
         let s = "Some random text with {{#shiftinclude 0:file.rs:10:20}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -458,6 +1221,7 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Range(LineRange::from(9..20)),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs:10:20}}",
             }]
@@ -467,7 +1231,7 @@ mod tests {
     #[test]
     fn test_find_links_with_line_number() {
         let s = "Some random text with {{#shiftinclude 0:file.rs:10}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -478,6 +1242,7 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Range(LineRange::from(9..10)),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs:10}}",
             }]
@@ -487,7 +1252,7 @@ mod tests {
     #[test]
     fn test_find_links_with_from_range() {
         let s = "Some random text with {{#shiftinclude 0:file.rs:10:}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -498,6 +1263,7 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Range(LineRange::from(9..)),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs:10:}}",
             }]
@@ -507,7 +1273,7 @@ mod tests {
     #[test]
     fn test_find_links_with_to_range() {
         let s = "Some random text with {{#shiftinclude 0:file.rs::20}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -518,6 +1284,7 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Range(LineRange::from(..20)),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs::20}}",
             }]
@@ -527,7 +1294,7 @@ mod tests {
     #[test]
     fn test_find_links_with_full_range() {
         let s = "Some random text with {{#shiftinclude 0:file.rs::}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -538,6 +1305,7 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Range(LineRange::from(..)),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs::}}",
             }]
@@ -547,7 +1315,7 @@ mod tests {
     #[test]
     fn test_find_links_with_no_range_specified() {
         let s = "Some random text with {{#shiftinclude 0:file.rs}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -558,6 +1326,7 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Range(LineRange::from(..)),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs}}",
             }]
@@ -567,7 +1336,7 @@ mod tests {
     #[test]
     fn test_find_links_with_anchor() {
         let s = "Some random text with {{#shiftinclude 0:file.rs:anchor}}...";
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
         assert_eq!(
             res,
@@ -578,17 +1347,641 @@ mod tests {
                     PathBuf::from("file.rs"),
                     RangeOrAnchor::Anchor(String::from("anchor")),
                     Shift::None,
+                    Options::default(),
                 ),
                 link_text: "{{#shiftinclude 0:file.rs:anchor}}",
             }]
         );
     }
 
+    #[test]
+    fn test_find_links_with_options() {
+        let s = "Some random text with {{#shiftinclude auto:file.rs:anchor line-numbers}}...";
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![Link {
+                start_index: 22,
+                end_index: 72,
+                link_type: LinkType::Include(
+                    PathBuf::from("file.rs"),
+                    RangeOrAnchor::Anchor(String::from("anchor")),
+                    Shift::Auto,
+                    Options {
+                        line_numbers: Some(true),
+                        ..Options::default()
+                    },
+                ),
+                link_text: "{{#shiftinclude auto:file.rs:anchor line-numbers}}",
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_links_include() {
+        let s = "Text {{#include file.rs:2:5}} and {{#rustdoc_include file.rs:main shift=auto}}...";
+        assert!(find_links(s, &Config::default())
+            .collect::<Vec<_>>()
+            .is_empty());
+
+        let cfg = Config {
+            handle_include: true,
+            ..Config::default()
+        };
+        let res = find_links(s, &cfg).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![
+                Link {
+                    start_index: 5,
+                    end_index: 29,
+                    link_type: LinkType::Include(
+                        PathBuf::from("file.rs"),
+                        RangeOrAnchor::Range(LineRange::from(1..5)),
+                        Shift::None,
+                        mdbook_options(Options::default()),
+                    ),
+                    link_text: "{{#include file.rs:2:5}}",
+                },
+                Link {
+                    start_index: 34,
+                    end_index: 78,
+                    link_type: LinkType::Include(
+                        PathBuf::from("file.rs"),
+                        RangeOrAnchor::Anchor(String::from("main")),
+                        Shift::None,
+                        Options {
+                            shift: Some(Shift::Auto),
+                            hidden_context: Some(true),
+                            ..mdbook_options(Options::default())
+                        },
+                    ),
+                    link_text: "{{#rustdoc_include file.rs:main shift=auto}}",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replace_all_include() {
+        let tmp = fixture(&[("a.rs", "// Copyright 2024\nfn a() {}\nfn b() {}\n")]);
+        let dir = tmp.path();
+        let cfg = Config {
+            handle_include: true,
+            line_numbers: true,
+            hidden_context: true,
+            rewrite_links: true,
+            skip_license: SkipLicense::Always,
+            replace: vec!["/fn/pub fn/".parse().unwrap()],
+            source_links: true,
+            repository_root: Some(dir.canonicalize().unwrap()),
+            repository_url: Some("https://github.com/u/r".to_string()),
+            ..Config::default()
+        };
+        // mdBook's directives ignore the configured defaults.
+        let start = "{{#include a.rs:3}} {{#include a.rs}}";
+        let end = "fn b() {} // Copyright 2024\nfn a() {}\nfn b() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        let start = "{{#rustdoc_include a.rs:3}}";
+        let end = "# // Copyright 2024\n# fn a() {}\nfn b() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        // Options written in the directive still apply.
+        let start = "{{#include a.rs:3 line-numbers config-replace}}";
+        let end = "3 | pub fn b() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        let start = "{{#shiftinclude 0:a.rs:3}}";
+        let end = "# // Copyright 2024\n# pub fn a() {}\n3 | pub fn b() {}\n\n[a.rs#L3](https://github.com/u/r/blob/main/a.rs#L3)\n";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+    }
+
+    #[test]
+    fn test_find_links_alias() {
+        let s = "Text {{#snippet file.rs:main}} and {{#code file.rs fence=text}}...";
+        assert!(find_links(s, &Config::default())
+            .collect::<Vec<_>>()
+            .is_empty());
+
+        let cfg = Config {
+            aliases: [
+                ("snippet".to_string(), "shift=auto fence=rust".to_string()),
+                ("code".to_string(), "fence=rust line-numbers".to_string()),
+            ]
+            .into(),
+            ..Config::default()
+        };
+        let res = find_links(s, &cfg).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![
+                Link {
+                    start_index: 5,
+                    end_index: 30,
+                    link_type: LinkType::Include(
+                        PathBuf::from("file.rs"),
+                        RangeOrAnchor::Anchor(String::from("main")),
+                        Shift::None,
+                        Options {
+                            shift: Some(Shift::Auto),
+                            fence: Some("rust".to_string()),
+                            ..Options::default()
+                        },
+                    ),
+                    link_text: "{{#snippet file.rs:main}}",
+                },
+                Link {
+                    start_index: 35,
+                    end_index: 63,
+                    link_type: LinkType::Include(
+                        PathBuf::from("file.rs"),
+                        RangeOrAnchor::Range(LineRange::from(..)),
+                        Shift::None,
+                        Options {
+                            line_numbers: Some(true),
+                            fence: Some("text".to_string()),
+                            ..Options::default()
+                        },
+                    ),
+                    link_text: "{{#code file.rs fence=text}}",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_links_with_var() {
+        let s = "Some random text with {{#shiftinclude 0:v${version}/file.rs}}...";
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![Link {
+                start_index: 22,
+                end_index: 61,
+                link_type: LinkType::Include(
+                    PathBuf::from("v${version}/file.rs"),
+                    RangeOrAnchor::Range(LineRange::from(..)),
+                    Shift::None,
+                    Options::default(),
+                ),
+                link_text: "{{#shiftinclude 0:v${version}/file.rs}}",
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_links_optional() {
+        let s = "Text {{#shiftinclude 2:?gen.rs:main}} {{#shiftinclude 0:gen.rs default=\"No output\"}}";
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![
+                Link {
+                    start_index: 5,
+                    end_index: 37,
+                    link_type: LinkType::Include(
+                        PathBuf::from("gen.rs"),
+                        RangeOrAnchor::Anchor(String::from("main")),
+                        Shift::Right(2),
+                        Options {
+                            optional: Some(true),
+                            ..Options::default()
+                        },
+                    ),
+                    link_text: "{{#shiftinclude 2:?gen.rs:main}}",
+                },
+                Link {
+                    start_index: 38,
+                    end_index: 84,
+                    link_type: LinkType::Include(
+                        PathBuf::from("gen.rs"),
+                        RangeOrAnchor::Range(LineRange::from(..)),
+                        Shift::None,
+                        Options {
+                            default: Some("No output".to_string()),
+                            ..Options::default()
+                        },
+                    ),
+                    link_text: "{{#shiftinclude 0:gen.rs default=\"No output\"}}",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replace_all_optional() {
+        let start = "A {{#shiftinclude 0:?missing.rs}}, B {{#shiftinclude 0:missing.rs optional}}, C {{#shiftinclude 0:missing.rs default=none}}, D {{#shiftinclude 0:missing.rs}}";
+        let end = "A , B , C none, D {{#shiftinclude 0:missing.rs}}";
+        assert_eq!(replace_all(start, "", "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_glob() {
+        let tmp = fixture(&[
+            ("examples/b.toml", "  [b]\n  x = 2\n"),
+            ("examples/a.toml", "  [a]\n  x = 1\n"),
+        ]);
+        let dir = tmp.path();
+        let start = "{{#shiftinclude auto:examples/*.toml header=\"# {path}\" fence=toml}}";
+        let end = "```toml\n# examples/a.toml\n[a]\nx = 1\n# examples/b.toml\n[b]\nx = 2\n```";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+
+        let start = "{{#shiftinclude 0:examples/*.toml:2}}";
+        let end = "  x = 1\n  x = 2";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+
+        let start =
+            "{{#shiftinclude 0:examples/*.none}} {{#shiftinclude 0:examples/*.none default=-}}";
+        let end = "{{#shiftinclude 0:examples/*.none}} -";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_glob_literal() {
+        let tmp = fixture(&[("[id].js", "export {};\n"), ("i.js", "let i;\n")]);
+        let dir = tmp.path();
+        // A file whose name has glob characters in it is included as it is.
+        let start = "{{#shiftinclude 0:[id].js}}";
+        assert_eq!(
+            replace_all(start, dir, "", 0, &Config::default()),
+            "export {};"
+        );
+        let start = "{{#shiftinclude 0:[ab].js}} {{#shiftinclude 0:[i].js}}";
+        let end = "{{#shiftinclude 0:[ab].js}} let i;";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_diff() {
+        let tmp = fixture(&[
+            (
+                "old.rs",
+                "mod m {\n    // ANCHOR: main\n    fn main() {\n        run(1);\n    }\n    // ANCHOR_END: main\n}\n",
+            ),
+            (
+                "new.rs",
+                "mod m {\n    // ANCHOR: main\n    fn main() {\n        run(2);\n    }\n    // ANCHOR_END: main\n}\n",
+            ),
+        ]);
+        let dir = tmp.path();
+        let start = "{{#shiftinclude diff auto:old.rs:main new.rs:main fence}}";
+        let end = "```diff\n--- old.rs\n+++ new.rs\n@@ -3,3 +3,3 @@\n fn main() {\n-    run(1);\n+    run(2);\n }\n```";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+
+        let start = "{{#shiftinclude diff 0:old.rs:4 ?missing.rs}}";
+        let end = "--- old.rs\n+++ missing.rs\n@@ -4 +0,0 @@\n-        run(1);";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_git() {
+        let tmp = fixture(&[("lib.rs", "fn f() {}\n")]);
+        let dir = tmp.path();
+        git(dir, &["init", "-q"]);
+        git(dir, &["add", "."]);
+        git(dir, &["commit", "-q", "-m", "init"]);
+        fs::write(dir.join("new.rs"), "fn g() {}\n").unwrap();
+        let cfg = Config {
+            repository_root: Some(dir.canonicalize().unwrap()),
+            ..Config::default()
+        };
+        let start = "{{#shiftinclude 0:git:HEAD:lib.rs}}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), "fn f() {}");
+        // A file that is missing at the revision can be optional.
+        let start = "{{#shiftinclude 0:git:HEAD:new.rs}} {{#shiftinclude 0:?git:HEAD:new.rs}}";
+        let end = "{{#shiftinclude 0:git:HEAD:new.rs}} ";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        let start = "{{#shiftinclude diff 0:?git:HEAD:new.rs new.rs}}";
+        let end = "--- HEAD:new.rs\n+++ new.rs\n@@ -0,0 +1 @@\n+fn g() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+    }
+
+    #[test]
+    fn test_find_links_command() {
+        let s = "{{#shiftinclude-cmd auto:cwd=examples lines=2: cargo run --example \"a b\" }}";
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            vec![Link {
+                start_index: 0,
+                end_index: s.len(),
+                link_type: LinkType::Command(
+                    "cargo run --example \"a b\"".to_string(),
+                    RangeOrAnchor::Range(LineRange::from(1..)),
+                    Shift::Auto,
+                    Options {
+                        cwd: Some("examples".to_string()),
+                        ..Options::default()
+                    },
+                ),
+                link_text: s,
+            }]
+        );
+        assert_eq!(
+            find_links("{{#shiftinclude-cmd 0:lines=2}}", &Config::default()).count(),
+            0
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_replace_all_command() {
+        let tmp = fixture(&[("sub/", "")]);
+        let dir = tmp.path();
+        let cfg = Config {
+            allow_commands: true,
+            root: dir.to_path_buf(),
+            ..Config::default()
+        };
+        let start = "{{#shiftinclude-cmd 2:printf 'a\\nb\\nc\\n'}}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), "  a\n  b\n  c");
+        let start = "{{#shiftinclude-cmd 0:cwd=sub lines=:1 pwd}}";
+        let want = dir.join("sub").canonicalize().unwrap();
+        assert_eq!(
+            replace_all(start, dir, "", 0, &cfg),
+            want.display().to_string()
+        );
+        // Commands are only run when allowed.
+        let start = "{{#shiftinclude-cmd 0:echo hi}}";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), start);
+    }
+
+    #[test]
+    fn test_replace_all_max_size() {
+        let tmp = fixture(&[("big.txt", &"0123456789\n".repeat(10))]);
+        let dir = tmp.path();
+        fs::write(dir.join("image.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let cfg = Config {
+            max_file_size: 100,
+            ..Config::default()
+        };
+        let start = "{{#shiftinclude 0:big.txt:1}}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), start);
+        let start = "{{#shiftinclude 0:big.txt:1 max-size=0}}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), "0123456789");
+        let start = "{{#shiftinclude 0:image.png}}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), start);
+    }
+
+    #[test]
+    fn test_parse_byte_range() {
+        assert_eq!(parse_byte_range(Some("0:256")), LineRange::from(0..256));
+        assert_eq!(parse_byte_range(Some("16")), LineRange::from(16..));
+        assert_eq!(parse_byte_range(Some("16:")), LineRange::from(16..));
+        assert_eq!(parse_byte_range(Some(":8")), LineRange::from(..8));
+        assert_eq!(parse_byte_range(None), LineRange::from(..));
+    }
+
+    #[test]
+    fn test_replace_all_hex() {
+        let tmp = fixture(&[]);
+        let dir = tmp.path();
+        fs::write(dir.join("sample.bin"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let start = "{{#shiftinclude 2:hex:sample.bin:0:8}}";
+        let end = "  00000000  89 50 4e 47 0d 0a 1a 0a                           |.PNG....|";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+
+        // Only the bytes in the range count towards the size limit.
+        let cfg = Config {
+            max_file_size: 8,
+            ..Config::default()
+        };
+        let start = "{{#shiftinclude 0:hex:sample.bin:12:16}}";
+        let end = "0000000c  49 48 44 52                                       |IHDR|";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        let start = "{{#shiftinclude 0:hex:sample.bin:4:}}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), start);
+    }
+
+    #[test]
+    fn test_replace_all_key() {
+        let tmp = fixture(&[
+            (
+                "config.json",
+                "{\n  \"server\": {\n    \"tls\": {\n      \"cert\": \"a.pem\"\n    }\n  }\n}\n",
+            ),
+            ("c.toml", "[x.dependencies]\nlibc = \"0.2\"\n"),
+        ]);
+        let dir = tmp.path();
+        let start = "{{#shiftinclude auto:config.json:key=/server/tls line-numbers}}";
+        let end = "3 | {\n4 |   \"cert\": \"a.pem\"\n5 | }";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        let start = "{{#shiftinclude auto:config.json:key=server.none}}";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), start);
+        // A re-serialized subtree has no line numbers to show.
+        let start = "{{#shiftinclude 0:c.toml:key=x line-numbers}}";
+        let end = "[dependencies]\nlibc = \"0.2\"";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_heading() {
+        let tmp = fixture(&[(
+            "guide.md",
+            "# Guide\n\n## Installation\n\nRun `make`.\n\n## Getting started\n\nRead on.\n",
+        )]);
+        let dir = tmp.path();
+        let start = "{{#shiftinclude 0:guide.md:heading=Installation}}";
+        let end = "## Installation\n\nRun `make`.";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        let start = "{{#shiftinclude 0:guide.md:heading=getting-started include-heading=false}}";
+        assert_eq!(
+            replace_all(start, dir, "", 0, &Config::default()),
+            "Read on."
+        );
+        let start = "{{#shiftinclude 0:\"guide.md:heading=Getting started\"}}";
+        let end = "## Getting started\n\nRead on.";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_headings() {
+        let tmp = fixture(&[("part.md", "# Part\n\n## Detail\n")]);
+        let dir = tmp.path();
+        let start = "# Book\n\n## Chapter\n\n{{#shiftinclude 0:part.md headings=auto}}\n";
+        let end = "# Book\n\n## Chapter\n\n### Part\n\n#### Detail\n";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        let start = "{{#shiftinclude 0:part.md headings=+1}}";
+        assert_eq!(
+            replace_all(start, dir, "", 0, &Config::default()),
+            "## Part\n\n### Detail"
+        );
+        // Text included in a code block is left alone.
+        let start = "```md\n{{#shiftinclude 0:part.md headings=+1}}\n```";
+        let end = "```md\n# Part\n\n## Detail\n```";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        // So is a code block in a list item.
+        let start = "- item\n\n    ```md\n    {{#shiftinclude 0:part.md headings=+1}}\n    ```";
+        let end = "- item\n\n    ```md\n    # Part\n\n## Detail\n    ```";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_rewrite_links() {
+        let tmp = fixture(&[
+            ("shared/img/", ""),
+            ("guide/", ""),
+            (
+                "shared/part.md",
+                "![Logo](img/logo.png)\n\n[Other](other.md#top) [Web](https://example.com)\n\n[ref]: ../guide/intro.md\n",
+            ),
+        ]);
+        let dir = tmp.path();
+        let start = "{{#shiftinclude 0:../shared/part.md rewrite-links}}";
+        let end = "![Logo](../shared/img/logo.png)\n\n[Other](../shared/other.md#top) [Web](https://example.com)\n\n[ref]: intro.md";
+        assert_eq!(
+            replace_all(start, dir.join("guide"), "", 0, &Config::default()),
+            end
+        );
+        let start = "{{#shiftinclude 0:../shared/part.md:1}}";
+        assert_eq!(
+            replace_all(start, dir.join("guide"), "", 0, &Config::default()),
+            "![Logo](img/logo.png)"
+        );
+    }
+
+    #[test]
+    fn test_replace_all_skip_license() {
+        let tmp = fixture(&[(
+            "lib.rs",
+            "// Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT\n\npub fn f() {}\n",
+        )]);
+        let dir = tmp.path();
+        // By default, the header is kept.
+        let start = "{{#shiftinclude 0:lib.rs:1:2}}";
+        let end = "// Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        let cfg = Config {
+            skip_license: SkipLicense::FullFile,
+            handle_include: true,
+            ..Config::default()
+        };
+        let start = "{{#shiftinclude 0:lib.rs}} {{#shiftinclude 0:lib.rs:1:2}}";
+        let end = "pub fn f() {} // Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        // mdBook's include only leaves it out when asked to.
+        let start = "{{#include lib.rs}} {{#include lib.rs skip-license}}";
+        let end = "// Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT\n\npub fn f() {} pub fn f() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+        let start = "{{#shiftinclude 0:lib.rs:1:4 skip-license}} {{#shiftinclude 0:lib.rs skip-license=false}}";
+        let end = "pub fn f() {} // Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT\n\npub fn f() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        let cfg = Config {
+            skip_license: SkipLicense::Always,
+            license_keywords: vec!["Apache".to_string()],
+            ..Config::default()
+        };
+        let start =
+            "{{#shiftinclude 0:lib.rs:1:4}} {{#shiftinclude 0:lib.rs:1:4 license-keyword=spdx}}";
+        let end = "// Copyright 2024 Example Ltd.\n// SPDX-License-Identifier: MIT\n\npub fn f() {} pub fn f() {}";
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+    }
+
+    #[test]
+    fn test_replace_all_filter() {
+        let tmp = fixture(&[(
+            "main.rs",
+            "fn main() {\n    #[allow(unused)]\n        let x = 1;\n    debug!(\"{x}\");\n}\n",
+        )]);
+        let dir = tmp.path();
+        // Filtered lines do not count towards the common indentation.
+        let start = r#"{{#shiftinclude auto:main.rs:2:4 hide="/^\s*#\[/" hide=/debug!/}}"#;
+        assert_eq!(
+            replace_all(start, dir, "", 0, &Config::default()),
+            "let x = 1;"
+        );
+        let start = r#"{{#shiftinclude auto:main.rs keep=/main|let/}}"#;
+        let end = "fn main() {\n        let x = 1;";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        // With hidden context, filtered lines are hidden instead.
+        let start = r#"{{#shiftinclude auto:main.rs:3:4 hide=/let/ hidden-context}}"#;
+        let end =
+            "# fn main() {\n#     #[allow(unused)]\n#         let x = 1;\ndebug!(\"{x}\");\n# }";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_replace_all_replace() {
+        let tmp = fixture(&[(
+            "client.rs",
+            "    #[allow(unused)]\n    let c = internal_api::Client::new(\"secret_x1\");\n",
+        )]);
+        let dir = tmp.path();
+        let cfg = Config {
+            replace: vec!["/secret_[a-z0-9]+/REDACTED/".parse().unwrap()],
+            ..Config::default()
+        };
+        let start = r#"{{#shiftinclude auto:client.rs replace="/internal_(\w+)/$1/" replace=/^\s*#\[allow.*//}}"#;
+        let end = r#"let c = api::Client::new("REDACTED");"#;
+        assert_eq!(replace_all(start, dir, "", 0, &cfg), end);
+    }
+
+    #[test]
+    fn test_replace_all_params() {
+        let tmp = fixture(&[("snippet.rs", "    let w = {{name}}::new({{port}});\n")]);
+        let dir = tmp.path();
+        let start = "{{#shiftinclude auto:snippet.rs param.name=\"Widget\" param.port=8080}}";
+        let end = "let w = Widget::new(8080);";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        // Unknown options are not parameters.
+        let start = "{{#shiftinclude auto:snippet.rs param.name=Widget port=8080}}";
+        let end = "let w = Widget::new({{port}});";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+        // Without any parameters, placeholders are left alone.
+        let start = "{{#shiftinclude auto:snippet.rs}}";
+        let end = "let w = {{name}}::new({{port}});";
+        assert_eq!(replace_all(start, dir, "", 0, &Config::default()), end);
+    }
+
+    #[test]
+    fn test_find_links_git() {
+        let s = "Text {{#shiftinclude auto:git:v1.2.0:src/lib.rs:anchor}} {{#shiftinclude 0:?git:main:a.rs}}";
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
+        println!("\nOUTPUT: {:?}\n", res);
+        assert_eq!(
+            res,
+            vec![
+                Link {
+                    start_index: 5,
+                    end_index: 56,
+                    link_type: LinkType::Include(
+                        PathBuf::from("src/lib.rs"),
+                        RangeOrAnchor::Anchor(String::from("anchor")),
+                        Shift::Auto,
+                        Options {
+                            rev: Some("v1.2.0".to_string()),
+                            ..Options::default()
+                        },
+                    ),
+                    link_text: "{{#shiftinclude auto:git:v1.2.0:src/lib.rs:anchor}}",
+                },
+                Link {
+                    start_index: 57,
+                    end_index: 91,
+                    link_type: LinkType::Include(
+                        PathBuf::from("a.rs"),
+                        RangeOrAnchor::Range(LineRange::from(..)),
+                        Shift::None,
+                        Options {
+                            rev: Some("main".to_string()),
+                            optional: Some(true),
+                            ..Options::default()
+                        },
+                    ),
+                    link_text: "{{#shiftinclude 0:?git:main:a.rs}}",
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_find_links_escaped_link() {
         let s = "Some random text with escaped playground \\{{#playground file.rs editable}} ...";
 
-        let res = find_links(s).collect::<Vec<_>>();
+        let res = find_links(s, &Config::default()).collect::<Vec<_>>();
         println!("\nOUTPUT: {:?}\n", res);
 
         assert_eq!(
@@ -0,0 +1,575 @@
+//! Helpers for working with the Markdown text of a chapter.
+
+use log::warn;
+use regex::{Captures, Regex};
+use std::{num::ParseIntError, ops::Range, str::FromStr, sync::LazyLock};
+
+/// A fenced code block that encloses some position in a chapter.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Fence {
+    /// The info string that follows the opening fence, such as `rust,editable`.
+    pub info: String,
+    /// Index just after the line that closes the block (or the end of the text if the block is
+    /// never closed).
+    pub end: usize,
+}
+
+impl Fence {
+    fn attributes(&self) -> impl Iterator<Item = &str> {
+        self.info
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+    }
+
+    /// The language of the code block, if given.
+    pub fn lang(&self) -> Option<&str> {
+        self.attributes().next().filter(|a| !a.contains('='))
+    }
+
+    /// The prefix for hidden lines given by a `hidelines=<prefix>` attribute, if any.
+    pub fn hidelines(&self) -> Option<&str> {
+        self.attributes().find_map(|a| a.strip_prefix("hidelines="))
+    }
+}
+
+/// The start of a list item, such as `- ` or `1. `, along with the spaces that follow it.
+static LIST_ITEM_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^ *(?:[-+*]|[0-9]{1,9}[.)]) {1,4}").unwrap());
+
+/// The list items that enclose each line of Markdown text, as the indentation of their content, so
+/// that fenced code blocks nested in list items can be recognised.
+#[derive(Default)]
+struct ListItems(Vec<usize>);
+
+impl ListItems {
+    /// Take account of `line`, which is outside any code block, and return the indentation of the
+    /// content of the innermost list item that it is in (or zero).
+    fn indent(&mut self, line: &str) -> usize {
+        if line.trim().is_empty() {
+            return self.current();
+        }
+        let spaces = line.len() - line.trim_start_matches(' ').len();
+        while self.0.last().is_some_and(|&indent| spaces < indent) {
+            self.0.pop();
+        }
+        if spaces <= self.current() + 3 {
+            if let Some(item) = LIST_ITEM_RE.find(line) {
+                self.0.push(item.end());
+            }
+        }
+        self.current()
+    }
+
+    fn current(&self) -> usize {
+        self.0.last().copied().unwrap_or(0)
+    }
+}
+
+/// A fenced code block opening line, such as ```` ```rust ````, in a list item whose content is
+/// indented by `indent` spaces (or zero outside any list item).
+fn fence_open(line: &str, indent: usize) -> Option<(char, usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if (line.len() - trimmed.len()).checked_sub(indent)? > 3 {
+        return None;
+    }
+    let c = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let count = trimmed.chars().take_while(|x| *x == c).count();
+    (count >= 3).then(|| (c, count, trimmed[count..].trim()))
+}
+
+/// Whether `line` closes a fenced code block opened with `count` repetitions of `c`.
+fn fence_close(line: &str, (c, count): (char, usize)) -> bool {
+    let trimmed = line.trim();
+    trimmed.len() >= count && trimmed.chars().all(|x| x == c)
+}
+
+/// Return the fenced code block of `s` that `index` falls inside, if any.
+pub fn enclosing_fence(s: &str, index: usize) -> Option<Fence> {
+    let mut open = None;
+    let mut items = ListItems::default();
+    let mut info = "";
+    let mut pos = 0;
+    for line in s.split_inclusive('\n') {
+        let line_end = pos + line.len();
+        if pos > index {
+            // A line after the one holding `index`.
+            if fence_close(line, open?) {
+                return Some(Fence {
+                    info: info.to_string(),
+                    end: line_end,
+                });
+            }
+        } else if line_end <= index {
+            // A line before the one holding `index`.
+            open = match open {
+                Some(fence) if fence_close(line, fence) => None,
+                Some(fence) => Some(fence),
+                None => fence_open(line, items.indent(line)).map(|(c, count, i)| {
+                    info = i;
+                    (c, count)
+                }),
+            };
+        } else if open.is_none() {
+            return None;
+        }
+        pos = line_end;
+    }
+    open.map(|_| Fence {
+        info: info.to_string(),
+        end: s.len(),
+    })
+}
+
+/// A heading in Markdown text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Heading {
+    /// Level of the heading, from 1 to 6.
+    pub level: usize,
+    /// Text of the heading, without any `{#id}` attribute.
+    pub text: String,
+    /// Identifier given by a `{#id}` attribute, if any.
+    pub id: Option<String>,
+    /// (0-based) index of the first line of the heading.
+    pub line: usize,
+    /// Number of lines that the heading takes up (more than one for a Setext heading).
+    pub len: usize,
+}
+
+impl Heading {
+    /// Whether `name` is the text of the heading, or its identifier (as given by a `{#id}`
+    /// attribute, or derived from the text in the same way as mdBook).
+    pub fn matches(&self, name: &str) -> bool {
+        self.text == name || self.id.clone().unwrap_or_else(|| slug(&self.text)) == name
+    }
+}
+
+/// Derive an identifier from the text of a heading, in the same way as mdBook.
+pub fn slug(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                Some(c.to_ascii_lowercase())
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// An ATX heading line, such as `## Installation`, giving its level and text.
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    // Drop any closing sequence of `#`s.
+    let text = rest.trim();
+    let unclosed = text.trim_end_matches('#');
+    let text = if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+        unclosed.trim_end()
+    } else {
+        text
+    };
+    Some((level, text))
+}
+
+/// A Setext heading underline, such as `===`, giving the level of the heading.
+fn setext_underline(line: &str) -> Option<usize> {
+    let trimmed = line.trim();
+    if line.len() - line.trim_start_matches(' ').len() > 3 || trimmed.is_empty() {
+        return None;
+    }
+    if trimmed.chars().all(|c| c == '=') {
+        Some(1)
+    } else if trimmed.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Split a trailing `{#id}` attribute from the text of a heading.
+fn split_heading_id(text: &str) -> (&str, Option<String>) {
+    if let Some((rest, attrs)) = text
+        .strip_suffix('}')
+        .and_then(|t| t.rsplit_once('{'))
+        .filter(|(rest, _)| rest.ends_with(' '))
+    {
+        if let Some(id) = attrs.split_whitespace().find_map(|a| a.strip_prefix('#')) {
+            return (rest.trim_end(), Some(id.to_string()));
+        }
+    }
+    (text, None)
+}
+
+/// Return the headings in `s`, skipping anything in fenced code blocks.
+pub fn headings(s: &str) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    let mut open = None;
+    let mut items = ListItems::default();
+    // Start of the paragraph that the current line continues, which a Setext underline turns into
+    // a heading.
+    let mut paragraph: Option<usize> = None;
+    let lines = s.lines().collect::<Vec<_>>();
+    for (num, line) in lines.iter().enumerate() {
+        if let Some(fence) = open {
+            if fence_close(line, fence) {
+                open = None;
+            }
+            continue;
+        }
+        if let Some((c, count, _)) = fence_open(line, items.indent(line)) {
+            open = Some((c, count));
+            paragraph = None;
+            continue;
+        }
+        let heading = if let Some((level, text)) = atx_heading(line) {
+            paragraph = None;
+            Some((level, text.to_string(), num, 1))
+        } else if let (Some(start), Some(level)) = (paragraph, setext_underline(line)) {
+            paragraph = None;
+            let text = lines[start..num]
+                .iter()
+                .map(|l| l.trim())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Some((level, text, start, num + 1 - start))
+        } else {
+            if line.trim().is_empty() {
+                paragraph = None;
+            } else if paragraph.is_none() {
+                paragraph = Some(num);
+            }
+            None
+        };
+        if let Some((level, text, line, len)) = heading {
+            let (text, id) = split_heading_id(&text);
+            headings.push(Heading {
+                level,
+                text: text.to_string(),
+                id,
+                line,
+                len,
+            });
+        }
+    }
+    headings
+}
+
+/// Indication of how to change the level of headings in included Markdown text.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum HeadingShift {
+    /// Change the level of every heading by the given amount.
+    By(isize),
+    /// Change the level of every heading so that the highest-level headings come just under the
+    /// heading that the text is included under.
+    Auto,
+}
+
+impl FromStr for HeadingShift {
+    type Err = ParseIntError;
+
+    /// Parse a heading shift, either `auto` or a (signed) number of levels.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(HeadingShift::Auto);
+        }
+        Ok(HeadingShift::By(s.parse()?))
+    }
+}
+
+/// Change the level of the headings in `s`, which is included under a heading of level `parent`
+/// (or 0 if there is no heading before it).  Levels are kept between 1 and 6.  Setext headings
+/// that end up below level 2 are rewritten as ATX headings.
+pub fn shift_headings(s: &str, shift: HeadingShift, parent: usize) -> String {
+    let headings = headings(s);
+    let delta = match shift {
+        HeadingShift::By(n) => n,
+        HeadingShift::Auto => match headings.iter().map(|h| h.level).min() {
+            Some(top) => parent as isize + 1 - top as isize,
+            None => 0,
+        },
+    };
+    if delta == 0 {
+        return s.to_string();
+    }
+
+    let lines = s.split('\n').collect::<Vec<_>>();
+    let mut out = Vec::<String>::new();
+    let mut next = 0;
+    for h in headings {
+        out.extend(lines[next..h.line].iter().map(|l| l.to_string()));
+        next = h.line + h.len;
+        let wanted = h.level as isize + delta;
+        let level = wanted.clamp(1, 6) as usize;
+        if level as isize != wanted {
+            warn!("Heading \"{}\" cannot go to level {wanted}", h.text);
+        }
+        let hashes = "#".repeat(level);
+        if h.len == 1 {
+            let line = lines[h.line];
+            let indent = &line[..line.len() - line.trim_start_matches(' ').len()];
+            let rest = line[indent.len()..].trim_start_matches('#');
+            out.push(format!("{indent}{hashes}{rest}"));
+            continue;
+        }
+        let (text, underline) = lines[h.line..next].split_at(h.len - 1);
+        if level <= 2 {
+            out.extend(text.iter().map(|l| l.to_string()));
+            let c = if level == 1 { "=" } else { "-" };
+            out.push(underline[0].replace(['=', '-'], c));
+        } else {
+            let text = text.iter().map(|l| l.trim()).collect::<Vec<_>>().join(" ");
+            out.push(format!("{hashes} {text}"));
+        }
+    }
+    out.extend(lines[next..].iter().map(|l| l.to_string()));
+    out.join("\n")
+}
+
+/// The destination of an inline link or image, such as `[text](dest "title")`.
+static INLINE_LINK_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(!?\[[^\]]*\]\(\s*)(<[^>]*>|[^)\s]+)").unwrap());
+/// The destination of a link reference definition, such as `[ref]: dest "title"`.
+static LINK_DEFINITION_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^( {0,3}\[[^\]]+\]:\s*)(<[^>]*>|\S+)").unwrap());
+
+/// Rewrite the destinations of links, images and link reference definitions in `s` with
+/// `rewrite`, which returns `None` for a destination that should be left as it is.  Anything in a
+/// fenced code block or a code span is left alone.
+pub fn rewrite_links(s: &str, rewrite: impl Fn(&str) -> Option<String>) -> String {
+    let replace = |caps: &Captures| {
+        let dest = &caps[2];
+        let new = match dest.strip_prefix('<').and_then(|d| d.strip_suffix('>')) {
+            Some(inner) => rewrite(inner).map(|d| format!("<{d}>")),
+            None => rewrite(dest),
+        };
+        format!("{}{}", &caps[1], new.as_deref().unwrap_or(dest))
+    };
+    let mut open = None;
+    let mut items = ListItems::default();
+    let mut out = Vec::new();
+    for line in s.split('\n') {
+        if let Some(fence) = open {
+            if fence_close(line, fence) {
+                open = None;
+            }
+            out.push(line.to_string());
+            continue;
+        }
+        if let Some((c, count, _)) = fence_open(line, items.indent(line)) {
+            open = Some((c, count));
+            out.push(line.to_string());
+            continue;
+        }
+        let line = LINK_DEFINITION_RE.replace(line, replace);
+        // Alternate pieces between backticks are in code spans.
+        let pieces = line.split('`').collect::<Vec<_>>();
+        let balanced = pieces.len() % 2 == 1;
+        let line = pieces
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| {
+                if i % 2 == 0 || !balanced {
+                    INLINE_LINK_RE.replace_all(piece, replace).into_owned()
+                } else {
+                    piece.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("`");
+        out.push(line);
+    }
+    out.join("\n")
+}
+
+/// A section of Markdown text that starts with a heading.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Section {
+    pub heading: Heading,
+    /// (0-based) indices of the lines after the heading and up to the next heading of the same or
+    /// a higher level, without any blank lines at either end.
+    pub body: Range<usize>,
+}
+
+/// Find the section of `s` under the first heading that matches `name`.
+pub fn find_section(s: &str, name: &str) -> Option<Section> {
+    let headings = headings(s);
+    let pos = headings.iter().position(|h| h.matches(name))?;
+    let heading = headings[pos].clone();
+    let lines = s.lines().collect::<Vec<_>>();
+    let mut end = headings[pos + 1..]
+        .iter()
+        .find(|h| h.level <= heading.level)
+        .map_or(lines.len(), |h| h.line);
+    let mut start = heading.line + heading.len;
+    while start < end && lines[start].trim().is_empty() {
+        start += 1;
+    }
+    while end > start && lines[end - 1].trim().is_empty() {
+        end -= 1;
+    }
+    Some(Section {
+        heading,
+        body: start..end,
+    })
+}
+
+/// Wrap `content` in a fenced code block with the given info string, using a fence that is longer
+/// than any backtick fence inside `content`.
+pub fn fenced(content: &str, info: &str) -> String {
+    let longest = content
+        .lines()
+        .map(|l| l.trim_start().chars().take_while(|c| *c == '`').count())
+        .max()
+        .unwrap_or_default();
+    let ticks = "`".repeat(longest.max(2) + 1);
+    format!("{ticks}{info}\n{content}\n{ticks}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enclosing_fence_test() {
+        let fence_end = |s: &str, idx| enclosing_fence(s, idx).map(|f| f.end);
+        let s = "Text\n```rust\n{{#include}}\n```\nMore\n";
+        let idx = s.find("{{").unwrap();
+        assert_eq!(fence_end(s, idx), Some(s.find("More").unwrap()));
+        assert_eq!(fence_end(s, 0), None);
+        assert_eq!(fence_end(s, s.find("More").unwrap()), None);
+
+        let s = "~~~~\n```\n{{#include}}\n~~~~~";
+        let idx = s.find("{{").unwrap();
+        assert_eq!(fence_end(s, idx), Some(s.len()));
+
+        let s = "````\n{{#include}}\n```\n";
+        assert_eq!(fence_end(s, s.find("{{").unwrap()), Some(s.len()));
+
+        let s = "Inline {{#include}} here\n";
+        assert_eq!(fence_end(s, s.find("{{").unwrap()), None);
+
+        // Fences in list items are indented along with the item's content.
+        let s = "- item\n\n    ```rust\n    {{#include}}\n    ```\nMore\n";
+        let idx = s.find("{{").unwrap();
+        assert_eq!(fence_end(s, idx), Some(s.find("More").unwrap()));
+        let s = "1. item\n   - sub\n\n       ```\n       {{#include}}\n       ```\n";
+        assert_eq!(fence_end(s, s.find("{{").unwrap()), Some(s.len()));
+        // Outside a list item, that is an indented code block instead.
+        let s = "Text\n\n    ```rust\n    {{#include}}\n    ```\n";
+        assert_eq!(fence_end(s, s.find("{{").unwrap()), None);
+        let s = "- item\n\nText\n\n    ```rust\n    {{#include}}\n    ```\n";
+        assert_eq!(fence_end(s, s.find("{{").unwrap()), None);
+    }
+
+    #[test]
+    fn fenced_test() {
+        assert_eq!(fenced("fn main() {}", "rust"), "```rust\nfn main() {}\n```");
+        assert_eq!(fenced("", ""), "```\n\n```");
+        assert_eq!(
+            fenced("Text\n  ```rust\n  code\n  ```", "md"),
+            "````md\nText\n  ```rust\n  code\n  ```\n````"
+        );
+    }
+
+    #[test]
+    fn headings_test() {
+        let s = "# Guide {#top}\n\nIntro\n\n## Installation ##\n\n```sh\n# not a heading\n```\n\nGetting\nstarted\n---\n\n#hashtag\n\n- item\n\n---\n";
+        let found = headings(s)
+            .into_iter()
+            .map(|h| (h.level, h.text, h.id, h.line, h.len))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![
+                (1, "Guide".to_string(), Some("top".to_string()), 0, 1),
+                (2, "Installation".to_string(), None, 4, 1),
+                (2, "Getting started".to_string(), None, 10, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn shift_headings_test() {
+        let s = "# Title\n\nText\n\n```md\n# Not a heading\n```\n\nSub\n---\n\n  ## Indented ##";
+        assert_eq!(
+            shift_headings(s, HeadingShift::By(1), 0),
+            "## Title\n\nText\n\n```md\n# Not a heading\n```\n\n### Sub\n\n  ### Indented ##"
+        );
+        assert_eq!(
+            shift_headings(s, HeadingShift::Auto, 2),
+            shift_headings(s, HeadingShift::By(2), 0)
+        );
+        assert_eq!(shift_headings(s, HeadingShift::Auto, 0), s);
+        assert_eq!(
+            shift_headings("Title\n===\n## Sub", HeadingShift::By(1), 0),
+            "Title\n---\n### Sub"
+        );
+        assert_eq!(
+            shift_headings("## Sub\n### Subsub", HeadingShift::By(-2), 0),
+            "# Sub\n# Subsub"
+        );
+        assert_eq!(shift_headings("Text", HeadingShift::Auto, 3), "Text");
+        assert_eq!("auto".parse(), Ok(HeadingShift::Auto));
+        assert_eq!("+2".parse(), Ok(HeadingShift::By(2)));
+        assert_eq!("-1".parse(), Ok(HeadingShift::By(-1)));
+    }
+
+    #[test]
+    fn rewrite_links_test() {
+        let rewrite = |s| rewrite_links(s, |url| Some(format!("../{url}")));
+        assert_eq!(
+            rewrite("See [a](a.md) and ![b](<b c.png> \"B\"), not `[c](c.md)`."),
+            "See [a](../a.md) and ![b](<../b c.png> \"B\"), not `[c](c.md)`."
+        );
+        assert_eq!(rewrite("[ref]: ref.md \"Ref\""), "[ref]: ../ref.md \"Ref\"");
+        assert_eq!(rewrite("```md\n[a](a.md)\n```"), "```md\n[a](a.md)\n```");
+        assert_eq!(
+            rewrite_links("[a](a.md) [b](http://b)", |url| {
+                (!url.starts_with("http")).then(|| format!("x/{url}"))
+            }),
+            "[a](x/a.md) [b](http://b)"
+        );
+    }
+
+    #[test]
+    fn find_section_test() {
+        let s = "# Guide\n\n## Install\n\nRun it.\n\n### Linux\n\nSudo.\n\n## Use {#usage}\n\nUse it.\n";
+        let section = |name| find_section(s, name).map(|s| (s.heading.line, s.body));
+        assert_eq!(section("Install"), Some((2, 4..9)));
+        assert_eq!(section("install"), Some((2, 4..9)));
+        assert_eq!(section("Linux"), Some((6, 8..9)));
+        assert_eq!(section("usage"), Some((10, 12..13)));
+        assert_eq!(section("Use"), Some((10, 12..13)));
+        assert_eq!(section("guide"), Some((0, 2..13)));
+        assert_eq!(section("Missing"), None);
+        assert_eq!(slug("Getting Started: v2.0!"), "getting-started-v20");
+    }
+
+    #[test]
+    fn fence_info_test() {
+        let fence = |info: &str| Fence {
+            info: info.to_string(),
+            end: 0,
+        };
+        assert_eq!(fence("rust,editable").lang(), Some("rust"));
+        assert_eq!(fence("rust,editable").hidelines(), None);
+        assert_eq!(fence("python hidelines=!!!").lang(), Some("python"));
+        assert_eq!(fence("python hidelines=!!!").hidelines(), Some("!!!"));
+        assert_eq!(fence("").lang(), None);
+
+        let s = "```python,hidelines=~\n{{#include}}\n```\n";
+        assert_eq!(
+            enclosing_fence(s, s.find("{{").unwrap()),
+            Some(fence("python,hidelines=~")).map(|f| Fence { end: s.len(), ..f })
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Options that can be given after the path in an include directive, such as
+//! `{{#shiftinclude auto:file.rs:main line-numbers}}`.
+
+use crate::markdown::HeadingShift;
+use crate::string::{Pattern, Replacement, Shift};
+use log::warn;
+use std::collections::BTreeMap;
+
+/// Per-directive options.  Options that are not given fall back to the book-wide configuration.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Options {
+    /// Amount to shift the included text by, overriding any shift given before the path.
+    pub shift: Option<Shift>,
+    /// Whether to prefix each line with its line number in the original file.
+    pub line_numbers: Option<bool>,
+    /// Whether to include the rest of the file as hidden lines.
+    pub hidden_context: Option<bool>,
+    /// Info string for a fenced code block to wrap the included text in.
+    pub fence: Option<String>,
+    /// Whether a missing file is acceptable (and renders as nothing).
+    pub optional: Option<bool>,
+    /// Text to render in place of a missing optional file.
+    pub default: Option<String>,
+    /// Git revision to read the included file at, instead of the working tree.
+    pub rev: Option<String>,
+    /// Template for a line to put before each file matched by a glob pattern, where `{path}` is
+    /// replaced by the path of the file.
+    pub header: Option<String>,
+    /// Directory (relative to the book root) to run an included command in.
+    pub cwd: Option<String>,
+    /// Number of seconds that an included command may run for.
+    pub timeout: Option<u64>,
+    /// Label of the encoding of included text that has no byte order mark, such as `latin1`.
+    pub encoding: Option<String>,
+    /// Largest file (in bytes) that can be included, or zero for no limit.
+    pub max_size: Option<u64>,
+    /// Whether a section selected by its heading includes the heading line itself.
+    pub include_heading: Option<bool>,
+    /// Amount to change the level of Markdown headings in the included text by.
+    pub headings: Option<HeadingShift>,
+    /// Whether to rewrite relative links in included Markdown to suit the including file.
+    pub rewrite_links: Option<bool>,
+    /// Whether to leave out the license header at the start of the included file.
+    pub skip_license: Option<bool>,
+    /// Word that a license header must hold, in place of those in the configuration.
+    pub license_keyword: Option<String>,
+    /// Patterns for lines to leave out of the included text.
+    pub hide: Vec<Pattern>,
+    /// Patterns for the only lines to include, if there are any.
+    pub keep: Vec<Pattern>,
+    /// Regex search-and-replaces to apply to the included lines, after any from the configuration.
+    pub replace: Vec<Replacement>,
+    /// Whether the search-and-replaces from the configuration apply to the included lines.
+    pub config_replace: Option<bool>,
+    /// Whether to emit a link back to the source repository under the included snippet.
+    pub source_link: Option<bool>,
+    /// Values for `{{name}}` placeholders in the included text, from `param.name=value` words.
+    pub params: BTreeMap<String, String>,
+}
+
+/// Prefix of the words that give template parameters rather than options.
+const PARAM_PREFIX: &str = "param.";
+
+/// Names of the options that can be given in a directive.
+const NAMES: &[&str] = &[
+    "shift",
+    "line-numbers",
+    "hidden-context",
+    "fence",
+    "optional",
+    "default",
+    "header",
+    "rev",
+    "cwd",
+    "timeout",
+    "encoding",
+    "max-size",
+    "include-heading",
+    "headings",
+    "rewrite-links",
+    "replace",
+    "hide",
+    "keep",
+    "skip-license",
+    "license-keyword",
+    "config-replace",
+    "source-link",
+];
+
+impl Options {
+    /// Whether `key` is the name of an option.
+    pub fn is_option(key: &str) -> bool {
+        NAMES.contains(&key) || key.starts_with(PARAM_PREFIX)
+    }
+
+    /// Parse options from the words that follow the path in a directive.
+    pub fn parse<S: AsRef<str>>(words: &[S]) -> Self {
+        let mut opts = Self::default();
+        opts.apply(words);
+        opts
+    }
+
+    /// Whether a missing file is acceptable.  Giving a default implies that the file is optional.
+    pub fn is_optional(&self) -> bool {
+        self.optional.unwrap_or(self.default.is_some())
+    }
+
+    /// Parse options from the given words, overriding any existing values.
+    pub fn apply<S: AsRef<str>>(&mut self, words: &[S]) {
+        for word in words {
+            let word = word.as_ref();
+            let (key, value) = match word.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (word, None),
+            };
+            match key {
+                "shift" => match value.map(str::parse) {
+                    Some(Ok(shift)) => self.shift = Some(shift),
+                    _ => warn!("Ignoring invalid shift option \"{word}\""),
+                },
+                "line-numbers" => self.line_numbers = parse_flag(key, value),
+                "hidden-context" => self.hidden_context = parse_flag(key, value),
+                "fence" => self.fence = Some(value.unwrap_or_default().to_string()),
+                "optional" => self.optional = parse_flag(key, value),
+                "default" => self.default = value.map(str::to_string),
+                "header" => self.header = value.map(str::to_string),
+                "rev" => self.rev = value.map(str::to_string),
+                "headings" => match value.map(str::parse) {
+                    Some(Ok(shift)) => self.headings = Some(shift),
+                    _ => warn!("Ignoring invalid headings option \"{word}\""),
+                },
+                "rewrite-links" => self.rewrite_links = parse_flag(key, value),
+                // Unlike other options, replacements add to those given before.
+                "replace" => match value.map(str::parse) {
+                    Some(Ok(r)) => self.replace.push(r),
+                    Some(Err(e)) => warn!("Ignoring invalid replace option \"{word}\": {e}"),
+                    None => warn!("Ignoring invalid replace option \"{word}\""),
+                },
+                "config-replace" => self.config_replace = parse_flag(key, value),
+                "source-link" => self.source_link = parse_flag(key, value),
+                "skip-license" => self.skip_license = parse_flag(key, value),
+                "license-keyword" => self.license_keyword = value.map(str::to_string),
+                "hide" | "keep" => match value.map(str::parse) {
+                    Some(Ok(p)) if key == "hide" => self.hide.push(p),
+                    Some(Ok(p)) => self.keep.push(p),
+                    Some(Err(e)) => warn!("Ignoring invalid {key} option \"{word}\": {e}"),
+                    None => warn!("Ignoring invalid {key} option \"{word}\""),
+                },
+                "include-heading" => self.include_heading = parse_flag(key, value),
+                "max-size" => match value.map(str::parse) {
+                    Some(Ok(size)) => self.max_size = Some(size),
+                    _ => warn!("Ignoring invalid max-size option \"{word}\""),
+                },
+                "encoding" => self.encoding = value.map(str::to_string),
+                "cwd" => self.cwd = value.map(str::to_string),
+                "timeout" => match value.map(str::parse) {
+                    Some(Ok(secs)) => self.timeout = Some(secs),
+                    _ => warn!("Ignoring invalid timeout option \"{word}\""),
+                },
+                _ => match (key.strip_prefix(PARAM_PREFIX), value) {
+                    (Some(name), Some(value)) if !name.is_empty() => {
+                        self.params.insert(name.to_string(), value.to_string());
+                    }
+                    (Some(_), _) => warn!("Ignoring invalid parameter \"{word}\""),
+                    (None, _) => warn!("Ignoring unknown option \"{word}\""),
+                },
+            }
+        }
+    }
+}
+
+/// Parse the value of an option that is either present (`opt`) or explicitly set (`opt=false`).
+fn parse_flag(key: &str, value: Option<&str>) -> Option<bool> {
+    match value {
+        None | Some("true") => Some(true),
+        Some("false") => Some(false),
+        Some(v) => {
+            warn!("Ignoring invalid value \"{v}\" for option \"{key}\"");
+            None
+        }
+    }
+}
+
+/// Split directive text into whitespace-separated words.  Double quotes group text that includes
+/// whitespace into a single word, and are removed; a backslash escapes a double quote.
+pub fn split_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            '\\' if quoted => {
+                let next = chars.next();
+                let w = word.get_or_insert_with(String::new);
+                match next {
+                    Some('"') => w.push('"'),
+                    Some(n) => {
+                        w.push('\\');
+                        w.push(n);
+                    }
+                    None => w.push('\\'),
+                }
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(w) = word.take() {
+                    words.push(w);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(w) = word {
+        words.push(w);
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_words_test() {
+        let tests: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("  a  b ", &["a", "b"]),
+            (
+                "0:file.rs:1:2 line-numbers",
+                &["0:file.rs:1:2", "line-numbers"],
+            ),
+            (r#"a key="some value" b"#, &["a", "key=some value", "b"]),
+            (r#"key="say \"hi\"""#, &[r#"key=say "hi""#]),
+            (r#"key="\d+""#, &[r"key=\d+"]),
+            (r#"key="""#, &["key="]),
+        ];
+        for (input, want) in tests {
+            assert_eq!(&split_words(input), want, "for input {input:?}");
+        }
+    }
+
+    #[test]
+    fn parse_options_test() {
+        assert_eq!(Options::parse::<&str>(&[]), Options::default());
+        assert_eq!(Options::parse(&["line-numbers"]).line_numbers, Some(true));
+        assert_eq!(
+            Options::parse(&["line-numbers=false"]).line_numbers,
+            Some(false)
+        );
+        assert_eq!(Options::parse(&["line-numbers=maybe"]).line_numbers, None);
+        assert_eq!(Options::parse(&["shift=auto"]).shift, Some(Shift::Auto));
+        assert_eq!(Options::parse(&["shift=-2"]).shift, Some(Shift::Left(2)));
+        assert_eq!(Options::parse(&["shift"]).shift, None);
+        assert_eq!(Options::parse(&["fence"]).fence, Some(String::new()));
+        assert_eq!(
+            Options::parse(&["fence=rust,editable"]).fence,
+            Some("rust,editable".to_string())
+        );
+
+        assert_eq!(
+            Options::parse(&["param.name=Widget", "param.port=8080", "param.name=Gadget"]).params,
+            BTreeMap::from([
+                ("name".to_string(), "Gadget".to_string()),
+                ("port".to_string(), "8080".to_string())
+            ])
+        );
+
+        let opts = Options::parse(&["replace=/a/b/", "replace=|c|d|", "replace=e"]);
+        assert_eq!(opts.replace.len(), 2);
+        assert_eq!(opts.replace[1].re.as_str(), "c");
+        let opts = Options::parse(&["config-replace=false", "source-link"]);
+        assert_eq!(opts.config_replace, Some(false));
+        assert_eq!(opts.source_link, Some(true));
+        let opts = Options::parse(&["hide=/a/", "keep=/b/", "hide=/c/", "keep"]);
+        assert_eq!(opts.hide.len(), 2);
+        assert_eq!(opts.keep.len(), 1);
+
+        let mut opts = Options::parse(&["shift=auto", "fence=rust"]);
+        opts.apply(&["shift=2", "line-numbers"]);
+        assert_eq!(
+            opts,
+            Options {
+                shift: Some(Shift::Right(2)),
+                line_numbers: Some(true),
+                fence: Some("rust".to_string()),
+                ..Options::default()
+            }
+        );
+    }
+}
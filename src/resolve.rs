@@ -0,0 +1,515 @@
+//! Resolution of the paths given in include directives to files on disk.
+
+use crate::config::Config;
+use anyhow::{bail, Context};
+use mdbook_preprocessor::errors::Result;
+use regex::{Captures, Regex};
+use std::{
+    env, io,
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// Prefix that marks a path as starting with a named directory from the `path-aliases` config.
+const ALIAS_PREFIX: char = '@';
+
+static VAR_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\$\{([^}]*)\}").unwrap());
+static SCHEME_RE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.-]*:").unwrap());
+
+/// Expand `${VAR}` placeholders in `s`, using values from the `vars` config or (failing that) the
+/// environment.  When sandboxed, only the environment variables in `allowed-env` can be used, so
+/// that a directive cannot put secrets from the environment into the build log.
+fn expand_vars(s: &str, cfg: &Config) -> Result<String> {
+    let mut missing = None;
+    let expanded = VAR_RE.replace_all(s, |cap: &Captures<'_>| {
+        let name = &cap[1];
+        let from_env = || {
+            if cfg.sandbox && !cfg.allowed_env.iter().any(|n| n == name) {
+                return None;
+            }
+            env::var(name).ok()
+        };
+        match cfg.vars.get(name).cloned().or_else(from_env) {
+            Some(value) => value,
+            None => {
+                missing.get_or_insert_with(|| name.to_string());
+                String::new()
+            }
+        }
+    });
+    if let Some(name) = missing {
+        bail!("Undefined variable \"{name}\"");
+    }
+    Ok(expanded.into_owned())
+}
+
+/// Resolve the path given in a directive to the file it refers to.
+///
+/// Any `${VAR}` placeholders are expanded first, from the `vars` config or the environment.
+///
+/// If the book has a language, a translation of the file for that language is used in preference
+/// to the file itself: for `dir/hello.rs`, either `dir/hello.<lang>.rs` or `dir/<lang>/hello.rs`.
+///
+/// - `@name/rest` is relative to the directory configured for `name` in `path-aliases`.
+/// - `/rest` is relative to the book root.
+/// - Any other path is relative to `base` (the directory of the including file), unless it does
+///   not exist there, in which case the `include-dirs` are searched in order.
+///
+/// If the `sandbox` config is set, the resolved path must be inside the book root or one of the
+/// `allowed-dirs`.
+pub fn resolve_path(base: &Path, path: &Path, cfg: &Config) -> Result<PathBuf> {
+    let path = PathBuf::from(expand_vars(&path.to_string_lossy(), cfg)?);
+    let mut target = locate(base, &path, cfg)?;
+    if let Some(lang) = cfg.language.as_deref().filter(|_| cfg.localized_sources) {
+        if let Some(translated) = translation(&target, lang) {
+            target = translated;
+        }
+    }
+    if cfg.sandbox {
+        check_sandbox(&target, cfg)?;
+    }
+    Ok(target)
+}
+
+/// Return the directories that `path` may be relative to, in order of preference, along with
+/// the remainder of the path relative to those directories.
+fn search_dirs<'a>(base: &Path, path: &'a Path, cfg: &Config) -> Result<(Vec<PathBuf>, &'a Path)> {
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::Normal(first)) => {
+            if let Some(name) = first.to_str().and_then(|f| f.strip_prefix(ALIAS_PREFIX)) {
+                let Some(dir) = cfg.path_aliases.get(name) else {
+                    bail!("Unknown path alias \"{ALIAS_PREFIX}{name}\"");
+                };
+                return Ok((vec![cfg.root.join(dir)], components.as_path()));
+            }
+        }
+        Some(Component::RootDir) => return Ok((vec![cfg.root.clone()], components.as_path())),
+        _ => {}
+    }
+    let dirs = std::iter::once(base.to_path_buf())
+        .chain(cfg.include_dirs.iter().map(|dir| cfg.root.join(dir)))
+        .collect();
+    Ok((dirs, path))
+}
+
+fn locate(base: &Path, path: &Path, cfg: &Config) -> Result<PathBuf> {
+    let (dirs, rest) = search_dirs(base, path, cfg)?;
+    let mut targets = dirs.iter().map(|dir| dir.join(rest));
+    let first = targets.next().expect("at least one directory");
+    if first.exists() {
+        return Ok(first);
+    }
+    Ok(targets.find(|t| t.exists()).unwrap_or(first))
+}
+
+/// Rewrite a relative URL in text from a file in the `from` directory so that it refers to the
+/// same place from a file in the `to` directory.  Returns `None` if the URL is not relative (or
+/// needs no change).
+pub fn relative_url(url: &str, from: &Path, to: &Path) -> Option<String> {
+    if url.is_empty() || url.starts_with(['/', '#', '?']) || SCHEME_RE.is_match(url) {
+        return None;
+    }
+    let (from, to) = (normalize(from), normalize(to));
+    if from == to {
+        return None;
+    }
+    let split = url.find(['?', '#']).unwrap_or(url.len());
+    let (path, suffix) = url.split_at(split);
+    let target = normalize(&from.join(path));
+    let common = target
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let parts = to
+        .components()
+        .skip(common)
+        .map(|_| "..".to_string())
+        .chain(
+            target
+                .components()
+                .skip(common)
+                .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect::<Vec<_>>();
+    let mut rel = if parts.is_empty() {
+        ".".to_string()
+    } else {
+        parts.join("/")
+    };
+    if path.ends_with('/') {
+        rel.push('/');
+    }
+    Some(format!("{rel}{suffix}"))
+}
+
+/// Remove `.` and `..` components from a path, without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether the path given in a directive is a glob pattern that may match several files.  A path
+/// with glob characters that names an existing file is taken literally.
+pub fn is_glob(base: &Path, path: &Path, cfg: &Config) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+        && !resolve_path(base, path, cfg).is_ok_and(|target| target.is_file())
+}
+
+/// Resolve a glob pattern given in a directive to the files it matches, in sorted order.
+///
+/// The pattern is relative to the same directories as [`resolve_path`] uses, with the first
+/// directory that holds any matches being used.  Each match is returned along with its path
+/// relative to that directory.
+pub fn resolve_glob(base: &Path, path: &Path, cfg: &Config) -> Result<Vec<(PathBuf, String)>> {
+    let path = PathBuf::from(expand_vars(&path.to_string_lossy(), cfg)?);
+    let (dirs, rest) = search_dirs(base, &path, cfg)?;
+    for dir in dirs {
+        let pattern = format!(
+            "{}/{}",
+            glob::Pattern::escape(&dir.to_string_lossy()),
+            rest.to_string_lossy()
+        );
+        let mut matches = glob::glob(&pattern)
+            .with_context(|| format!("Invalid glob pattern {}", path.display()))?
+            .filter_map(|m| m.ok())
+            .filter(|m| m.is_file())
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            continue;
+        }
+        matches.sort();
+        if cfg.sandbox {
+            for m in &matches {
+                check_sandbox(m, cfg)?;
+            }
+        }
+        return Ok(matches
+            .into_iter()
+            .map(|m| {
+                let rel = m
+                    .strip_prefix(&dir)
+                    .unwrap_or(&m)
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                (m, rel)
+            })
+            .collect());
+    }
+    Ok(Vec::new())
+}
+
+/// Find a translation of `target` for the given language, if one exists.
+fn translation(target: &Path, lang: &str) -> Option<PathBuf> {
+    let dir = target.parent()?;
+    let name = target.file_name()?;
+    let stem = target.file_stem()?.to_string_lossy();
+    let translated_name = match target.extension() {
+        Some(ext) => format!("{stem}.{lang}.{}", ext.to_string_lossy()),
+        None => format!("{stem}.{lang}"),
+    };
+    [dir.join(translated_name), dir.join(lang).join(name)]
+        .into_iter()
+        .find(|t| t.is_file())
+}
+
+/// Resolve the directory to run an included command in, which is the book root or `dir` relative
+/// to it.
+pub fn resolve_command_dir(dir: Option<&str>, cfg: &Config) -> Result<PathBuf> {
+    let dir = match dir {
+        Some(dir) => cfg.root.join(expand_vars(dir, cfg)?),
+        None => cfg.root.clone(),
+    };
+    if cfg.sandbox {
+        check_sandbox(&dir, cfg)?;
+    }
+    Ok(dir)
+}
+
+/// Check that `target` (after following any symlinks) is inside the book root or one of the
+/// `allowed-dirs`.
+fn check_sandbox(target: &Path, cfg: &Config) -> Result<()> {
+    // A missing file is checked by where it would be, as it may still be read from git history.
+    let canonical = canonicalize_lenient(target)
+        .with_context(|| format!("Could not canonicalize {}", target.display()))?;
+    let allowed = std::iter::once(cfg.root.clone())
+        .chain(cfg.allowed_dirs.iter().map(|dir| cfg.root.join(dir)))
+        .filter_map(|dir| dir.canonicalize().ok());
+    for dir in allowed {
+        if canonical.starts_with(dir) {
+            return Ok(());
+        }
+    }
+    bail!(
+        "{} is outside the book root and the allowed directories",
+        canonical.display()
+    )
+}
+
+/// Canonicalize `path`, which need not exist.  The nearest ancestor that does exist is
+/// canonicalized (following any symlinks), and the rest of the path is added to it with any `..`
+/// components resolved lexically.
+pub fn canonicalize_lenient(path: &Path) -> io::Result<PathBuf> {
+    let mut rest = Vec::new();
+    let mut existing = path;
+    loop {
+        match existing.canonicalize() {
+            Ok(mut canonical) => {
+                for component in rest.into_iter().rev() {
+                    match component {
+                        Component::ParentDir => {
+                            canonical.pop();
+                        }
+                        Component::CurDir => {}
+                        c => canonical.push(c),
+                    }
+                }
+                return Ok(canonical);
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let (Some(parent), Some(last)) =
+                    (existing.parent(), existing.components().next_back())
+                else {
+                    return Err(e);
+                };
+                if last == Component::CurDir {
+                    return Err(e);
+                }
+                rest.push(last);
+                existing = if parent.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    parent
+                };
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+
+    #[test]
+    fn resolve_path_test() {
+        let tmp = fixture(&[
+            ("src/nested/local.rs", ""),
+            ("examples/example.rs", ""),
+            ("shared/example.rs", ""),
+            ("shared/shared.rs", ""),
+        ]);
+        let root = tmp.path();
+        let cfg = Config {
+            root: root.to_path_buf(),
+            include_dirs: vec!["examples".into(), "shared".into()],
+            path_aliases: [("ex".to_string(), "examples".into())].into(),
+            ..Config::default()
+        };
+        let base = root.join("src/nested");
+        let resolve = |p: &str| resolve_path(&base, Path::new(p), &cfg).unwrap();
+
+        assert_eq!(resolve("local.rs"), base.join("local.rs"));
+        assert_eq!(resolve("example.rs"), root.join("examples/example.rs"));
+        assert_eq!(resolve("shared.rs"), root.join("shared/shared.rs"));
+        assert_eq!(resolve("missing.rs"), base.join("missing.rs"));
+        assert_eq!(resolve("/shared/shared.rs"), root.join("shared/shared.rs"));
+        assert_eq!(resolve("@ex/example.rs"), root.join("examples/example.rs"));
+        assert_eq!(resolve("@ex/sub/a.rs"), root.join("examples/sub/a.rs"));
+        assert!(resolve_path(&base, Path::new("@unknown/a.rs"), &cfg).is_err());
+    }
+
+    #[test]
+    fn translation_test() {
+        let tmp = fixture(&[
+            ("hello.rs", ""),
+            ("hello.de.rs", ""),
+            ("fr/hello.rs", ""),
+            ("README", ""),
+            ("README.de", ""),
+        ]);
+        let root = tmp.path();
+        let mut cfg = Config {
+            language: Some("de".to_string()),
+            ..Config::default()
+        };
+        let resolve = |p: &str, cfg: &Config| resolve_path(root, Path::new(p), cfg).unwrap();
+        assert_eq!(resolve("hello.rs", &cfg), root.join("hello.de.rs"));
+        assert_eq!(resolve("README", &cfg), root.join("README.de"));
+        cfg.language = Some("fr".to_string());
+        assert_eq!(resolve("hello.rs", &cfg), root.join("fr/hello.rs"));
+        assert_eq!(resolve("README", &cfg), root.join("README"));
+        cfg.language = Some("es".to_string());
+        assert_eq!(resolve("hello.rs", &cfg), root.join("hello.rs"));
+        cfg.language = Some("de".to_string());
+        cfg.localized_sources = false;
+        assert_eq!(resolve("hello.rs", &cfg), root.join("hello.rs"));
+    }
+
+    #[test]
+    fn expand_vars_test() {
+        let cfg = Config {
+            vars: [("version".to_string(), "2".to_string())].into(),
+            ..Config::default()
+        };
+        env::set_var("SHIFTINCLUDE_TEST_VAR", "env");
+        let expand = |s: &str| expand_vars(s, &cfg);
+        assert_eq!(expand("a/b.rs").unwrap(), "a/b.rs");
+        assert_eq!(expand("v${version}/b.rs").unwrap(), "v2/b.rs");
+        assert_eq!(
+            expand("${SHIFTINCLUDE_TEST_VAR}/v${version}.rs").unwrap(),
+            "env/v2.rs"
+        );
+        assert!(expand("${SHIFTINCLUDE_TEST_UNDEFINED}/b.rs").is_err());
+        // When sandboxed, only allowed environment variables can be used.
+        let mut sandboxed = Config {
+            sandbox: true,
+            ..cfg.clone()
+        };
+        let err = expand_vars("${SHIFTINCLUDE_TEST_VAR}/b.rs", &sandboxed).unwrap_err();
+        assert!(!err.to_string().contains("env"), "{err}");
+        assert_eq!(expand_vars("v${version}.rs", &sandboxed).unwrap(), "v2.rs");
+        sandboxed.allowed_env = vec!["SHIFTINCLUDE_TEST_VAR".to_string()];
+        assert_eq!(
+            expand_vars("${SHIFTINCLUDE_TEST_VAR}/b.rs", &sandboxed).unwrap(),
+            "env/b.rs"
+        );
+        assert_eq!(
+            resolve_path(Path::new("base"), Path::new("v${version}/a.rs"), &cfg).unwrap(),
+            Path::new("base/v2/a.rs")
+        );
+    }
+
+    #[test]
+    fn resolve_glob_test() {
+        let tmp = fixture(&[
+            ("src/", ""),
+            ("examples/b.toml", ""),
+            ("examples/a.toml", ""),
+            ("examples/c.rs", ""),
+            ("examples/[c].rs", ""),
+            ("examples/sub/d.toml", ""),
+        ]);
+        let root = tmp.path();
+        let cfg = Config {
+            root: root.to_path_buf(),
+            include_dirs: vec!["examples".into()],
+            ..Config::default()
+        };
+        let base = root.join("src");
+        let resolve = |p: &str| {
+            resolve_glob(&base, Path::new(p), &cfg)
+                .unwrap()
+                .into_iter()
+                .map(|(_, rel)| rel)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(is_glob(&base, Path::new("examples/*.toml"), &cfg));
+        assert!(!is_glob(&base, Path::new("examples/a.toml"), &cfg));
+        assert!(!is_glob(&base, Path::new("[c].rs"), &cfg));
+        assert!(is_glob(&base, Path::new("[a].toml"), &cfg));
+        assert_eq!(
+            resolve("../examples/*.toml"),
+            vec!["../examples/a.toml", "../examples/b.toml"]
+        );
+        assert_eq!(resolve("/examples/**/*.toml").len(), 3);
+        assert_eq!(resolve("*.toml"), vec!["a.toml", "b.toml"]);
+        assert!(resolve("*.none").is_empty());
+        assert_eq!(
+            resolve_glob(&base, Path::new("/examples/[a-b].toml"), &cfg).unwrap()[1].0,
+            root.join("examples/b.toml")
+        );
+    }
+
+    #[test]
+    fn relative_url_test() {
+        let from = Path::new("/book/src/shared/parts");
+        let to = Path::new("/book/src/guide");
+        let rel = |url| relative_url(url, from, to);
+        assert_eq!(
+            rel("img/a.png").as_deref(),
+            Some("../shared/parts/img/a.png")
+        );
+        assert_eq!(rel("../b.md#top").as_deref(), Some("../shared/b.md#top"));
+        assert_eq!(
+            rel("./c.md?x=1").as_deref(),
+            Some("../shared/parts/c.md?x=1")
+        );
+        assert_eq!(rel("../../guide/d.md").as_deref(), Some("d.md"));
+        assert_eq!(rel("../../guide/").as_deref(), Some("./"));
+        assert_eq!(rel("https://example.com/a.png"), None);
+        assert_eq!(rel("mailto:me@example.com"), None);
+        assert_eq!(rel("/abs.png"), None);
+        assert_eq!(rel("#anchor"), None);
+        assert_eq!(
+            relative_url("a.png", to, Path::new("/book/src/./guide")),
+            None
+        );
+    }
+
+    #[test]
+    fn canonicalize_lenient_test() {
+        let tmp = fixture(&[("a/b.rs", "")]);
+        let root = tmp.path().canonicalize().unwrap();
+        let canonical = |p: &str| canonicalize_lenient(&tmp.path().join(p)).unwrap();
+        assert_eq!(canonical("a/b.rs"), root.join("a/b.rs"));
+        assert_eq!(canonical("a/c/d.rs"), root.join("a/c/d.rs"));
+        assert_eq!(
+            canonical("x/../../y.rs"),
+            root.parent().unwrap().join("y.rs")
+        );
+    }
+
+    #[test]
+    fn sandbox_test() {
+        let tmp = fixture(&[
+            ("book/src/local.rs", ""),
+            ("allowed/a.rs", ""),
+            ("forbidden/f.rs", ""),
+        ]);
+        let root = tmp.path().join("book");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(tmp.path().join("forbidden/f.rs"), root.join("src/link.rs"))
+            .unwrap();
+        let mut cfg = Config {
+            root: root.clone(),
+            allowed_dirs: vec!["../allowed".into()],
+            ..Config::default()
+        };
+        let base = root.join("src");
+        let resolve = |p: &str, cfg: &Config| resolve_path(&base, Path::new(p), cfg);
+
+        // Without the sandbox, anything goes.
+        assert!(resolve("../../forbidden/f.rs", &cfg).is_ok());
+
+        cfg.sandbox = true;
+        assert!(resolve("local.rs", &cfg).is_ok());
+        assert!(resolve("missing.rs", &cfg).is_ok());
+        assert!(resolve("../../allowed/a.rs", &cfg).is_ok());
+        assert!(resolve("../../forbidden/f.rs", &cfg).is_err());
+        assert!(resolve("/../forbidden/f.rs", &cfg).is_err());
+        // Missing files are checked by where they would be.
+        assert!(resolve("../../forbidden/missing.rs", &cfg).is_err());
+        assert!(resolve("gone/../../../forbidden/f.rs", &cfg).is_err());
+        assert!(resolve("gone/../../../allowed/missing.rs", &cfg).is_ok());
+        #[cfg(unix)]
+        assert!(resolve("link.rs", &cfg).is_err());
+    }
+}
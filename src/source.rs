@@ -0,0 +1,492 @@
+//! Reading the contents of included files, and the output of included commands.
+
+use crate::config::Config;
+use crate::options::Options;
+use crate::resolve::canonicalize_lenient;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE};
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    ops::{Bound, RangeBounds},
+    path::Path,
+    process::{Child, Command, Output, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Interval between checks on whether a command has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Number of bytes at the start of a file to check for signs that it is binary.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Read the contents of the file at `target`, either from the working tree or (if the options
+/// give a git revision) from the book's repository at that revision, and decode them as text.
+/// Files larger than the `max-size` option or `max-file-size` config (unless zero) are rejected.
+pub fn read_source(target: &Path, opts: &Options, cfg: &Config) -> io::Result<String> {
+    decode(&read_bytes(target, opts, cfg)?, opts.encoding.as_deref())
+}
+
+/// Read the raw contents of the file at `target`, as for [`read_source`].
+pub fn read_bytes(target: &Path, opts: &Options, cfg: &Config) -> io::Result<Vec<u8>> {
+    read_byte_range(target, opts, cfg, ..).map(|(_, bytes)| bytes)
+}
+
+/// Read the given range of bytes of the file at `target`, as for [`read_source`], returning the
+/// offset of the first byte along with the bytes.  Only the bytes in the range are read from the
+/// working tree, and only they count towards the size limit.
+pub fn read_byte_range<R: RangeBounds<usize>>(
+    target: &Path,
+    opts: &Options,
+    cfg: &Config,
+    range: R,
+) -> io::Result<(usize, Vec<u8>)> {
+    let max_size = opts.max_size.unwrap_or(cfg.max_file_size);
+    let start = match range.start_bound() {
+        Bound::Included(&n) => n,
+        Bound::Excluded(&n) => n + 1,
+        Bound::Unbounded => 0,
+    };
+    let len = match range.end_bound() {
+        Bound::Included(&n) => Some((n + 1).saturating_sub(start)),
+        Bound::Excluded(&n) => Some(n.saturating_sub(start)),
+        Bound::Unbounded => None,
+    };
+    // Read no more than is needed to tell that the range is too large, so that a file with no end
+    // does not stop the build.
+    let limit = match (len, max_size) {
+        (Some(len), 0) => len as u64,
+        (Some(len), max) => (len as u64).min(max + 1),
+        (None, 0) => u64::MAX,
+        (None, max) => max + 1,
+    };
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is larger than the limit of {max_size} bytes",
+                target.display()
+            ),
+        )
+    };
+    let bytes = match &opts.rev {
+        Some(rev) => {
+            let blob = Blob::find(target, rev, cfg.repository_root.as_deref())?;
+            // The size is known up front, so a blob that is too large is never read.
+            let in_range = blob.size.saturating_sub(start as u64);
+            let in_range = len.map_or(in_range, |len| in_range.min(len as u64));
+            if max_size > 0 && in_range > max_size {
+                return Err(too_large());
+            }
+            blob.read(start as u64, limit)?
+        }
+        None => {
+            let mut file = fs::File::open(target)?;
+            file.seek(SeekFrom::Start(start as u64))?;
+            let mut bytes = Vec::new();
+            file.take(limit).read_to_end(&mut bytes)?;
+            bytes
+        }
+    };
+    if max_size > 0 && bytes.len() as u64 > max_size {
+        return Err(too_large());
+    }
+    Ok((start, bytes))
+}
+
+/// Decode `bytes` as text, with all line endings normalized to `\n`.  A byte order mark gives the
+/// encoding (and is removed); otherwise the text is in the encoding with the given label, or UTF-8.
+/// Text (other than UTF-16) that holds a NUL byte near its start is taken to be binary, and rejected.
+pub fn decode(bytes: &[u8], encoding: Option<&str>) -> io::Result<String> {
+    let (encoding, bytes) = match Encoding::for_bom(bytes) {
+        Some((encoding, bom_len)) => (encoding, &bytes[bom_len..]),
+        None => {
+            let label = encoding.unwrap_or("utf-8");
+            let encoding = Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("unknown encoding \"{label}\""),
+                )
+            })?;
+            (encoding, bytes)
+        }
+    };
+    let wide = encoding == UTF_16LE || encoding == UTF_16BE;
+    if !wide && bytes.iter().take(BINARY_CHECK_LEN).any(|&b| b == 0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "content appears to be binary",
+        ));
+    }
+    let text = encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("text is not valid {}", encoding.name()),
+            )
+        })?;
+    Ok(normalize_line_endings(&text))
+}
+
+/// Convert `\r\n` and lone `\r` line endings to `\n`.
+fn normalize_line_endings(s: &str) -> String {
+    s.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// A file as it was at a git revision of the book's repository.
+struct Blob<'a> {
+    repo: &'a Path,
+    /// The `<rev>:<path>` name of the file in the repository.
+    object: String,
+    /// Size of the file in bytes.
+    size: u64,
+}
+
+impl<'a> Blob<'a> {
+    /// Find the file at `target` as it was at the given git revision of the repository at `repo`.
+    /// The file need not exist in the working tree (nor its directory), but it must be inside the
+    /// repository.  A file that is missing at the revision gives a `NotFound` error.
+    fn find(target: &Path, rev: &str, repo: Option<&'a Path>) -> io::Result<Self> {
+        if rev.starts_with('-') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid git revision \"{rev}\""),
+            ));
+        }
+        let repo = repo.ok_or_else(|| io::Error::other("the book is not in a git repository"))?;
+        let rel_path = canonicalize_lenient(target)?
+            .strip_prefix(repo)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is outside the repository", target.display()),
+                )
+            })?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let tree = format!("{rev}^{{tree}}");
+        if !git(
+            repo,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                "--end-of-options",
+                &tree,
+            ],
+        )?
+        .status
+        .success()
+        {
+            return Err(io::Error::other(format!("unknown git revision \"{rev}\"")));
+        }
+        let object = format!("{rev}:{rel_path}");
+        let output = git(repo, &["cat-file", "-s", "--end-of-options", &object])?;
+        if !output.status.success() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{rel_path} does not exist at revision {rev}"),
+            ));
+        }
+        let size = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::other(format!("unexpected size of {object}: {e}")))?;
+        Ok(Self { repo, object, size })
+    }
+
+    /// Read up to `limit` bytes of the file, from offset `start`, without holding any more of it
+    /// in memory.
+    fn read(&self, start: u64, limit: u64) -> io::Result<Vec<u8>> {
+        let mut child = Command::new("git")
+            .arg("-C")
+            .arg(self.repo)
+            .args(["cat-file", "blob", "--end-of-options", &self.object])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        io::copy(&mut (&mut stdout).take(start), &mut io::sink())?;
+        let mut bytes = Vec::new();
+        stdout.take(limit).read_to_end(&mut bytes)?;
+        // Closing the pipe stops git if there is more of the file than is wanted.
+        let status = child.wait()?;
+        if !status.success() && (bytes.len() as u64) < limit {
+            return Err(io::Error::other(format!(
+                "git cat-file blob {} failed ({status})",
+                self.object
+            )));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Run git in `repo` with the given arguments, returning its output.
+fn git(repo: &Path, args: &[&str]) -> io::Result<Output> {
+    Command::new("git").arg("-C").arg(repo).args(args).output()
+}
+
+/// Run `cmd` with the system shell in `dir`, returning its standard output.  The command (along
+/// with any processes it starts) is killed if it runs, or holds its output open, for longer than
+/// `timeout`, and a failure exit status is an error, as is more than `max_size` bytes of output
+/// (unless zero).  The output is decoded in the same way as the contents of a file.
+pub fn run_command(
+    cmd: &str,
+    dir: &Path,
+    timeout: Duration,
+    max_size: u64,
+    opts: &Options,
+) -> io::Result<String> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let mut command = Command::new(shell);
+    command
+        .args([flag, cmd])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Put the command in a process group of its own, so that everything it starts can be killed.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn()?;
+
+    // Read the output in the background, so that a command with a lot of output does not block
+    // on a full pipe while we wait for it.
+    let (tx, rx) = mpsc::channel();
+    let reader = |pipe: Box<dyn Read + Send>, is_stdout: bool, max_size: Option<u64>| {
+        let tx = tx.clone();
+        let cmd = cmd.to_string();
+        thread::spawn(move || {
+            // Stop reading once the output is too large, which closes the pipe.
+            let mut buf = Vec::new();
+            let limit = max_size.map_or(u64::MAX, |max| max + 1);
+            let result = pipe
+                .take(limit)
+                .read_to_end(&mut buf)
+                .and_then(|_| match max_size {
+                    Some(max) if buf.len() as u64 > max => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("output of `{cmd}` is larger than the limit of {max} bytes"),
+                    )),
+                    _ => Ok(buf),
+                });
+            let _ = tx.send((is_stdout, result));
+        });
+    };
+    reader(
+        Box::new(child.stdout.take().expect("stdout is piped")),
+        true,
+        Some(max_size).filter(|&max| max > 0),
+    );
+    reader(
+        Box::new(child.stderr.take().expect("stderr is piped")),
+        false,
+        None,
+    );
+
+    let deadline = Instant::now() + timeout;
+    let timed_out = |child: &mut Child| {
+        kill(child);
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("`{cmd}` did not finish within {}s", timeout.as_secs_f64()),
+        )
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() > deadline {
+            return Err(timed_out(&mut child));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    // Processes left running in the background may still hold the output open.
+    let (mut stdout, mut stderr) = (None, None);
+    while stdout.is_none() || stderr.is_none() {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((true, output)) => stdout = Some(output?),
+            Ok((false, output)) => stderr = Some(output?),
+            Err(_) => return Err(timed_out(&mut child)),
+        }
+    }
+    let (stdout, stderr) = (stdout.unwrap(), stderr.unwrap());
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "`{cmd}` failed ({status}): {}",
+            String::from_utf8_lossy(&stderr).trim()
+        )));
+    }
+    decode(&stdout, opts.encoding.as_deref())
+}
+
+/// Kill `child`, along with (on Unix) the rest of its process group.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: `kill` has no memory safety requirements; a negative pid names a process group.
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixture, git};
+
+    #[test]
+    fn read_at_revision_test() {
+        let tmp = fixture(&[
+            ("repo/src/lib.rs", "old\n"),
+            ("repo/old/gone.rs", "gone\n"),
+            ("other/secret.txt", "SECRET\n"),
+        ]);
+        let dir = tmp.path().join("repo");
+        let other = tmp.path().join("other");
+        for repo in [&dir, &other] {
+            git(repo, &["init", "-q"]);
+            git(repo, &["add", "."]);
+            git(repo, &["commit", "-q", "-m", "old"]);
+            git(repo, &["tag", "v1"]);
+        }
+        fs::write(dir.join("src/lib.rs"), "new\n").unwrap();
+        fs::remove_dir_all(dir.join("old")).unwrap();
+
+        let cfg = Config {
+            repository_root: Some(dir.canonicalize().unwrap()),
+            ..Config::default()
+        };
+        let target = dir.join("src/lib.rs");
+        let at = |rev: &str| Options {
+            rev: Some(rev.to_string()),
+            ..Options::default()
+        };
+        assert_eq!(
+            read_source(&target, &Options::default(), &cfg).unwrap(),
+            "new\n"
+        );
+        assert_eq!(read_source(&target, &at("v1"), &cfg).unwrap(), "old\n");
+        // The size limit is checked before the file is read.
+        let limited = Options {
+            max_size: Some(3),
+            ..at("v1")
+        };
+        let err = read_source(&target, &limited, &cfg).unwrap_err();
+        assert!(err.to_string().contains("limit of 3 bytes"), "{err}");
+        assert_eq!(
+            read_byte_range(&target, &limited, &cfg, 1..3).unwrap(),
+            (1, b"ld".to_vec())
+        );
+        assert_eq!(
+            read_byte_range(&target, &limited, &cfg, 2..).unwrap(),
+            (2, b"d\n".to_vec())
+        );
+        let err = read_source(&target, &at("v2"), &cfg).unwrap_err();
+        assert_ne!(err.kind(), io::ErrorKind::NotFound);
+        let err = read_source(&dir.join("src/missing.rs"), &at("v1"), &cfg).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        // The file's directory need not exist any more.
+        let gone = dir.join("old/gone.rs");
+        assert_eq!(read_source(&gone, &at("v1"), &cfg).unwrap(), "gone\n");
+        // Files outside the repository, and revisions that look like options, are rejected.
+        let secret = dir.join("../other/secret.txt");
+        assert!(read_source(&secret, &at("HEAD"), &cfg).is_err());
+        let secret = other.join("secret.txt");
+        assert!(read_source(&secret, &at("HEAD"), &cfg).is_err());
+        assert!(read_source(&target, &at("--output=x"), &cfg).is_err());
+        assert!(read_source(&target, &at("v1"), &Config::default()).is_err());
+    }
+
+    #[test]
+    fn read_bytes_test() {
+        let tmp = fixture(&[("data.txt", "0123456789")]);
+        let target = tmp.path().join("data.txt");
+        let limit = |max_size| Options {
+            max_size: Some(max_size),
+            ..Options::default()
+        };
+        let cfg = Config::default();
+        assert_eq!(read_bytes(&target, &limit(0), &cfg).unwrap().len(), 10);
+        assert_eq!(read_bytes(&target, &limit(10), &cfg).unwrap().len(), 10);
+        let err = read_bytes(&target, &limit(9), &cfg).unwrap_err();
+        assert!(err.to_string().contains("limit of 9 bytes"), "{err}");
+        let read = |range| read_byte_range(&target, &limit(4), &cfg, range).unwrap();
+        assert_eq!(read(2..6), (2, b"2345".to_vec()));
+        assert_eq!(read(8..20), (8, b"89".to_vec()));
+        assert_eq!(read(20..30), (20, Vec::new()));
+        assert!(read_byte_range(&target, &limit(4), &cfg, 2..).is_err());
+    }
+
+    #[test]
+    fn decode_test() {
+        assert_eq!(decode(b"a\r\nb\rc\n", None).unwrap(), "a\nb\nc\n");
+        assert_eq!(decode(b"\xef\xbb\xbfkey=1", None).unwrap(), "key=1");
+        assert_eq!(
+            decode(b"\xff\xfek\0=\0\xe9\0\r\0\n\0", None).unwrap(),
+            "k=\u{e9}\n"
+        );
+        assert_eq!(
+            decode(b"\xfe\xff\0k\0=\0\xe9", Some("latin1")).unwrap(),
+            "k=\u{e9}"
+        );
+        assert_eq!(decode(b"caf\xe9", Some("latin1")).unwrap(), "caf\u{e9}");
+        assert!(decode(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", None)
+            .unwrap_err()
+            .to_string()
+            .contains("binary"));
+        assert_eq!(
+            decode(b"caf\xe9", None).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            decode(b"cafe", Some("klingon")).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_command_test() {
+        let dir = std::env::temp_dir();
+        let timeout = Duration::from_secs(10);
+        let opts = Options::default();
+        assert_eq!(
+            run_command("echo hello", &dir, timeout, 0, &opts).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            run_command("pwd", Path::new("/"), timeout, 0, &opts).unwrap(),
+            "/\n"
+        );
+        let err = run_command("echo oops >&2; exit 3", &dir, timeout, 0, &opts).unwrap_err();
+        assert!(err.to_string().contains("oops"), "{err}");
+        let err = run_command("sleep 5", &dir, Duration::from_millis(100), 0, &opts).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        // Background processes that hold the output open are also timed out, and killed.
+        let tmp = fixture(&[]);
+        let start = Instant::now();
+        let cmd = "(sleep 1; touch late) & echo hi";
+        let err = run_command(cmd, tmp.path(), Duration::from_millis(200), 0, &opts).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        thread::sleep(Duration::from_millis(1500));
+        assert!(!tmp.path().join("late").exists());
+        // Output beyond the size limit is not read.
+        let err = run_command("yes", &dir, timeout, 1000, &opts).unwrap_err();
+        assert!(err.to_string().contains("limit of 1000 bytes"), "{err}");
+        assert_eq!(
+            run_command("echo hello", &dir, timeout, 6, &opts).unwrap(),
+            "hello\n"
+        );
+    }
+}
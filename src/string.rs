@@ -1,7 +1,11 @@
 use regex::Regex;
 use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::num::ParseIntError;
 use std::ops::Bound::{Excluded, Included, Unbounded};
-use std::ops::RangeBounds;
+use std::ops::{Range, RangeBounds};
+use std::str::FromStr;
 use std::sync::LazyLock;
 
 /// Indication of whether to shift included text.
@@ -14,6 +18,49 @@ pub enum Shift {
     Auto,
 }
 
+impl FromStr for Shift {
+    type Err = ParseIntError;
+
+    /// Parse a shift amount, either `auto` or a (signed) number of characters to shift right.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Shift::Auto);
+        }
+        let shift: isize = s.parse()?;
+        Ok(match shift.cmp(&0) {
+            Ordering::Greater => Shift::Right(shift as usize),
+            Ordering::Equal => Shift::None,
+            Ordering::Less => Shift::Left(-shift as usize),
+        })
+    }
+}
+
+/// A line of included text, along with its (0-based) line number in the original text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Line {
+    pub num: usize,
+    pub text: String,
+    /// Whether the line is only included as hidden context.
+    pub hidden: bool,
+}
+
+impl Line {
+    fn new(num: usize, text: &str) -> Self {
+        Self {
+            num,
+            text: text.to_string(),
+            hidden: false,
+        }
+    }
+
+    fn new_hidden(num: usize, text: &str) -> Self {
+        Self {
+            hidden: true,
+            ..Self::new(num, text)
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 enum ExplicitShift {
     None,
@@ -71,31 +118,113 @@ fn shift_line(l: &str, shift: ExplicitShift) -> Cow<'_, str> {
     }
 }
 
-fn shift_lines(lines: &[String], shift: Shift) -> Vec<Cow<'_, str>> {
-    let shift = calculate_shift(lines, shift);
-    lines.iter().map(|l| shift_line(l, shift)).collect()
+/// Shift all of the given (non-hidden) lines left or right.  Hidden lines are left unchanged.
+pub fn shift_lines(lines: &[Line], shift: Shift) -> Vec<Line> {
+    let text = lines
+        .iter()
+        .filter(|l| !l.hidden)
+        .map(|l| l.text.clone())
+        .collect::<Vec<_>>();
+    let shift = calculate_shift(&text, shift);
+    lines
+        .iter()
+        .map(|l| Line {
+            text: if l.hidden {
+                l.text.clone()
+            } else {
+                shift_line(&l.text, shift).into_owned()
+            },
+            ..l.clone()
+        })
+        .collect()
+}
+
+/// Prefix the text of each (non-hidden) line with its (1-based) line number in the original text,
+/// padded to the width of the widest number.
+pub fn number_lines(lines: &[Line]) -> Vec<Line> {
+    let width = lines
+        .iter()
+        .filter(|l| !l.hidden)
+        .map(|l| (l.num + 1).to_string().len())
+        .max()
+        .unwrap_or_default();
+    lines
+        .iter()
+        .map(|l| {
+            if l.hidden {
+                return l.clone();
+            }
+            let gutter = format!("{:>width$} |", l.num + 1);
+            let text = if l.text.is_empty() {
+                gutter
+            } else {
+                format!("{gutter} {}", l.text)
+            };
+            Line { text, ..l.clone() }
+        })
+        .collect()
+}
+
+/// Mark hidden lines by prefixing them with `prefix`.
+pub fn hide_lines(lines: &[Line], prefix: &str) -> Vec<Line> {
+    lines
+        .iter()
+        .map(|l| Line {
+            text: if l.hidden {
+                format!("{prefix}{}", l.text)
+            } else {
+                l.text.clone()
+            },
+            ..l.clone()
+        })
+        .collect()
 }
 
-/// Take a range of lines from a string, shifting all lines left or right.
-pub fn take_lines_with_shift<R: RangeBounds<usize>>(s: &str, range: R, shift: Shift) -> String {
+/// Join the text of the given lines back together.
+pub fn join_lines(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|l| l.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Return the range of (0-based) line numbers covered by the given (non-hidden) lines, if any.
+pub fn line_span(lines: &[Line]) -> Option<Range<usize>> {
+    let mut visible = lines.iter().filter(|l| !l.hidden);
+    let first = visible.next()?;
+    let last = visible.next_back().unwrap_or(first);
+    Some(first.num..last.num + 1)
+}
+
+/// Take a range of lines from a string.
+pub fn take_lines<R: RangeBounds<usize>>(s: &str, range: R) -> Vec<Line> {
     let start = match range.start_bound() {
         Excluded(&n) => n + 1,
         Included(&n) => n,
         Unbounded => 0,
     };
-    let lines = s.lines().skip(start);
-    let retained = match range.end_bound() {
-        Excluded(end) => lines
-            .take(end.saturating_sub(start))
-            .map(|l| l.to_string())
-            .collect::<Vec<_>>(),
-        Included(end) => lines
-            .take((end + 1).saturating_sub(start))
-            .map(|l| l.to_string())
-            .collect::<Vec<_>>(),
-        Unbounded => lines.map(|l| l.to_string()).collect::<Vec<_>>(),
+    let lines = s.lines().enumerate().skip(start);
+    let lines: Box<dyn Iterator<Item = (usize, &str)>> = match range.end_bound() {
+        Excluded(end) => Box::new(lines.take(end.saturating_sub(start))),
+        Included(end) => Box::new(lines.take((end + 1).saturating_sub(start))),
+        Unbounded => Box::new(lines),
     };
-    shift_lines(&retained, shift).join("\n")
+    lines.map(|(num, l)| Line::new(num, l)).collect()
+}
+
+/// Take all of the lines from a string, marking those outside the range as hidden.
+pub fn take_lines_with_hidden_context<R: RangeBounds<usize>>(s: &str, range: R) -> Vec<Line> {
+    s.lines()
+        .enumerate()
+        .map(|(num, l)| {
+            if range.contains(&num) {
+                Line::new(num, l)
+            } else {
+                Line::new_hidden(num, l)
+            }
+        })
+        .collect()
 }
 
 static ANCHOR_START: LazyLock<Regex> =
@@ -103,13 +232,13 @@ static ANCHOR_START: LazyLock<Regex> =
 static ANCHOR_END: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"ANCHOR_END:\s*(?P<anchor_name>[\w_-]+)").unwrap());
 
-/// Take anchored lines from a string, shifting all lines left or right.
-/// Lines containing anchor are ignored.
-pub fn take_anchored_lines_with_shift(s: &str, anchor: &str, shift: Shift) -> String {
-    let mut retained = Vec::<String>::new();
+/// Take anchored lines from a string.  Lines containing anchor are ignored, so the line numbers of
+/// the returned lines need not be contiguous.
+pub fn take_anchored_lines(s: &str, anchor: &str) -> Vec<Line> {
+    let mut retained = Vec::<Line>::new();
     let mut anchor_found = false;
 
-    for l in s.lines() {
+    for (num, l) in s.lines().enumerate() {
         if anchor_found {
             match ANCHOR_END.captures(l) {
                 Some(cap) => {
@@ -119,7 +248,7 @@ pub fn take_anchored_lines_with_shift(s: &str, anchor: &str, shift: Shift) -> St
                 }
                 None => {
                     if !ANCHOR_START.is_match(l) {
-                        retained.push(l.to_string());
+                        retained.push(Line::new(num, l));
                     }
                 }
             }
@@ -130,13 +259,293 @@ pub fn take_anchored_lines_with_shift(s: &str, anchor: &str, shift: Shift) -> St
         }
     }
 
-    shift_lines(&retained, shift).join("\n")
+    retained
+}
+
+/// Take all of the lines from a string, marking those outside the anchored sections as hidden.
+/// Lines containing any anchor are ignored.
+pub fn take_anchored_lines_with_hidden_context(s: &str, anchor: &str) -> Vec<Line> {
+    let mut retained = Vec::<Line>::new();
+    let mut within_anchored_section = false;
+
+    for (num, l) in s.lines().enumerate() {
+        if within_anchored_section {
+            match ANCHOR_END.captures(l) {
+                Some(cap) => {
+                    if &cap["anchor_name"] == anchor {
+                        within_anchored_section = false;
+                    }
+                }
+                None => {
+                    if !ANCHOR_START.is_match(l) {
+                        retained.push(Line::new(num, l));
+                    }
+                }
+            }
+        } else if let Some(cap) = ANCHOR_START.captures(l) {
+            if &cap["anchor_name"] == anchor {
+                within_anchored_section = true;
+            }
+        } else if !ANCHOR_END.is_match(l) {
+            retained.push(Line::new_hidden(num, l));
+        }
+    }
+
+    retained
+}
+
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_-]*)\s*\}\}").unwrap());
+
+/// The result of filling in `{{name}}` placeholders.
+pub struct Filled {
+    pub lines: Vec<Line>,
+    /// Names of placeholders that have no value, which are left as they are.
+    pub missing: BTreeSet<String>,
+    /// Names of values that are not used by any placeholder.
+    pub unused: BTreeSet<String>,
+}
+
+/// Replace each `{{name}}` placeholder in the given lines with the value for `name`.
+pub fn fill_placeholders(lines: &[Line], params: &BTreeMap<String, String>) -> Filled {
+    let mut missing = BTreeSet::new();
+    let mut unused = params.keys().cloned().collect::<BTreeSet<_>>();
+    let lines = lines
+        .iter()
+        .map(|l| {
+            let text = PLACEHOLDER.replace_all(&l.text, |cap: &regex::Captures| {
+                let name = &cap[1];
+                match params.get(name) {
+                    Some(value) => {
+                        unused.remove(name);
+                        value.clone()
+                    }
+                    None => {
+                        missing.insert(name.to_string());
+                        cap[0].to_string()
+                    }
+                }
+            });
+            Line {
+                text: text.into_owned(),
+                ..l.clone()
+            }
+        })
+        .collect();
+    Filled {
+        lines,
+        missing,
+        unused,
+    }
+}
+
+/// A regex search-and-replace, written as `/pattern/replacement/`.  Any character that is not
+/// alphanumeric, whitespace or a backslash can be used in place of `/`, and the delimiter is
+/// escaped with a backslash where it appears in the pattern or replacement.  The replacement can
+/// refer to capture groups as `$1` or `${name}`.
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    pub re: Regex,
+    pub rep: String,
+}
+
+impl PartialEq for Replacement {
+    fn eq(&self, other: &Self) -> bool {
+        self.re.as_str() == other.re.as_str() && self.rep == other.rep
+    }
+}
+
+impl FromStr for Replacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (delim, parts) = split_delimited(s)?;
+        match parts.as_slice() {
+            [pattern, rep, end] if end.is_empty() => Ok(Self {
+                re: Regex::new(pattern).map_err(|e| e.to_string())?,
+                rep: rep.clone(),
+            }),
+            _ => Err(format!(
+                "\"{s}\" is not of the form {delim}pattern{delim}replacement{delim}"
+            )),
+        }
+    }
+}
+
+/// A regex that a line can be matched against, written as `/pattern/` (with the same choice of
+/// delimiter as for a [`Replacement`]).
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (delim, parts) = split_delimited(s)?;
+        match parts.as_slice() {
+            [pattern, end] if end.is_empty() => {
+                Ok(Self(Regex::new(pattern).map_err(|e| e.to_string())?))
+            }
+            _ => Err(format!("\"{s}\" is not of the form {delim}pattern{delim}")),
+        }
+    }
+}
+
+/// Split text such as `/a/b/` at the delimiter that it starts with, giving the delimiter and the
+/// parts after it (the last of which is empty if the text ends with the delimiter).  A backslash
+/// escapes the delimiter, and is otherwise kept.
+fn split_delimited(s: &str) -> Result<(char, Vec<String>), String> {
+    let delim = match s.chars().next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' => c,
+        _ => return Err(format!("\"{s}\" does not start with a delimiter such as /")),
+    };
+    let mut parts = vec![String::new()];
+    let mut chars = s[delim.len_utf8()..].chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some(n) if n == delim => part.push(n),
+                Some(n) => {
+                    part.push('\\');
+                    part.push(n);
+                }
+                None => part.push('\\'),
+            },
+            c if c == delim => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    Ok((delim, parts))
+}
+
+/// Filter out the visible lines that match any of the `hide` patterns, or (if there are any `keep`
+/// patterns) that match none of the `keep` patterns.  Filtered lines are dropped, or if
+/// `as_hidden` is set, are kept as hidden lines.  Lines that are already hidden are left alone.
+pub fn filter_lines(
+    lines: &[Line],
+    hide: &[Pattern],
+    keep: &[Pattern],
+    as_hidden: bool,
+) -> Vec<Line> {
+    lines
+        .iter()
+        .filter_map(|l| {
+            let filtered = !l.hidden
+                && (hide.iter().any(|p| p.0.is_match(&l.text))
+                    || (!keep.is_empty() && !keep.iter().any(|p| p.0.is_match(&l.text))));
+            match (filtered, as_hidden) {
+                (false, _) => Some(l.clone()),
+                (true, true) => Some(Line {
+                    hidden: true,
+                    ..l.clone()
+                }),
+                (true, false) => None,
+            }
+        })
+        .collect()
+}
+
+impl<'de> serde::Deserialize<'de> for Replacement {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Apply each replacement in turn to every line.  A line that is emptied by the replacements is
+/// dropped altogether, so that a replacement can remove lines as well as change them.
+pub fn replace_lines<'a>(
+    lines: &[Line],
+    replacements: impl IntoIterator<Item = &'a Replacement> + Clone,
+) -> Vec<Line> {
+    lines
+        .iter()
+        .filter_map(|l| {
+            let mut text = Cow::from(l.text.as_str());
+            for r in replacements.clone() {
+                if let Cow::Owned(s) = r.re.replace_all(&text, r.rep.as_str()) {
+                    text = Cow::Owned(s);
+                }
+            }
+            if text.trim().is_empty() && !l.text.trim().is_empty() {
+                return None;
+            }
+            Some(Line {
+                text: text.into_owned(),
+                ..l.clone()
+            })
+        })
+        .collect()
+}
+
+/// Number of bytes shown on each line of a hex dump.
+const HEX_ROW_LEN: usize = 16;
+
+/// Render bytes that start at the given offset in a file as a hex dump, with lines in the style of
+/// `hexdump -C`: the offset of the first byte on the line, the bytes in hex, then the bytes as ASCII
+/// (with `.` for bytes that are not printable).  Each line is numbered by its position in a dump of
+/// the whole file.
+pub fn hex_lines(bytes: &[u8], start: usize) -> Vec<Line> {
+    bytes
+        .chunks(HEX_ROW_LEN)
+        .enumerate()
+        .map(|(i, row)| {
+            let offset = start + i * HEX_ROW_LEN;
+            let hex = (0..HEX_ROW_LEN)
+                .map(|j| {
+                    let sep = if j == HEX_ROW_LEN / 2 { "  " } else { " " };
+                    match row.get(j) {
+                        Some(b) => format!("{sep}{b:02x}"),
+                        None => format!("{sep}  "),
+                    }
+                })
+                .collect::<String>();
+            let ascii = row
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            Line::new(
+                offset / HEX_ROW_LEN,
+                &format!("{offset:08x} {hex}  |{ascii}|"),
+            )
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn take_lines_with_shift<R: RangeBounds<usize>>(s: &str, range: R, shift: Shift) -> String {
+        join_lines(&shift_lines(&take_lines(s, range), shift))
+    }
+
+    fn take_anchored_lines_with_shift(s: &str, anchor: &str, shift: Shift) -> String {
+        join_lines(&shift_lines(&take_anchored_lines(s, anchor), shift))
+    }
+
+    #[test]
+    fn parse_shift_test() {
+        assert_eq!("auto".parse(), Ok(Shift::Auto));
+        assert_eq!("0".parse(), Ok(Shift::None));
+        assert_eq!("2".parse(), Ok(Shift::Right(2)));
+        assert_eq!("-4".parse(), Ok(Shift::Left(4)));
+        assert!("left".parse::<Shift>().is_err());
+    }
+
     #[test]
     fn common_leading_ws_test() {
         let tests = [
@@ -236,6 +645,148 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_replacement_test() {
+        let r: Replacement = "/secret_[a-z0-9]+/REDACTED/".parse().unwrap();
+        assert_eq!(r.re.as_str(), "secret_[a-z0-9]+");
+        assert_eq!(r.rep, "REDACTED");
+        let r: Replacement = r"|a\|b\d|c/d|".parse().unwrap();
+        assert_eq!(r.re.as_str(), r"a|b\d");
+        assert_eq!(r.rep, "c/d");
+        let r: Replacement = "/x//".parse().unwrap();
+        assert_eq!(r.rep, "");
+        assert!("abc".parse::<Replacement>().is_err());
+        assert!("/abc/".parse::<Replacement>().is_err());
+        assert!("/a/b/c".parse::<Replacement>().is_err());
+        assert!("/(/b/".parse::<Replacement>().is_err());
+    }
+
+    #[test]
+    fn filter_lines_test() {
+        let s = "fn f() {\n    #[cfg(debug)]\n    log::debug!(\"x\");\n    run();\n}";
+        let hide = [r"/^\s*#\[/".parse().unwrap(), "/debug!/".parse().unwrap()];
+        assert_eq!(
+            join_lines(&filter_lines(&take_lines(s, ..), &hide, &[], false)),
+            "fn f() {\n    run();\n}"
+        );
+        let keep = ["/run|[{}]/".parse().unwrap()];
+        let lines = filter_lines(&take_lines(s, ..), &[], &keep, false);
+        assert_eq!(join_lines(&lines), "fn f() {\n    run();\n}");
+        assert_eq!(lines[1].num, 3);
+        // Filtered lines can be kept as hidden lines, and lines that are already hidden are kept.
+        let lines = filter_lines(&take_lines_with_hidden_context(s, 1..4), &hide, &[], true);
+        assert_eq!(
+            lines.iter().map(|l| l.hidden).collect::<Vec<_>>(),
+            [true, true, true, false, true]
+        );
+        assert!("/(/".parse::<Pattern>().is_err());
+        assert!("/a/b/".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn replace_lines_test() {
+        let replacements = [
+            "/secret_[a-z0-9]+/REDACTED/".parse().unwrap(),
+            r"/\binternal_(\w+)::/${1}::/".parse().unwrap(),
+            r"/^\s*#\[allow\(.*\)\]$//".parse().unwrap(),
+        ];
+        let lines = take_lines(
+            "#[allow(unused)]\nuse internal_api::Client;\n\nlet key = \"secret_ab12\";",
+            ..,
+        );
+        let replaced = replace_lines(&lines, &replacements);
+        assert_eq!(
+            join_lines(&replaced),
+            "use api::Client;\n\nlet key = \"REDACTED\";"
+        );
+        assert_eq!(replaced[0].num, 1);
+    }
+
+    #[test]
+    fn fill_placeholders_test() {
+        let lines = take_lines(
+            "struct {{name}};\nlet x = {{ name }}::new({{port}});\n{{#include}}",
+            ..,
+        );
+        let params = BTreeMap::from([
+            ("name".to_string(), "Widget".to_string()),
+            ("host".to_string(), "localhost".to_string()),
+        ]);
+        let filled = fill_placeholders(&lines, &params);
+        assert_eq!(
+            join_lines(&filled.lines),
+            "struct Widget;\nlet x = Widget::new({{port}});\n{{#include}}"
+        );
+        assert_eq!(filled.missing, BTreeSet::from(["port".to_string()]));
+        assert_eq!(filled.unused, BTreeSet::from(["host".to_string()]));
+    }
+
+    #[test]
+    fn hex_lines_test() {
+        let bytes = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\x01\0";
+        assert_eq!(
+            join_lines(&hex_lines(bytes, 0)),
+            "00000000  89 50 4e 47 0d 0a 1a 0a  00 00 00 0d 49 48 44 52  |.PNG........IHDR|\n\
+             00000010  00 00 01 00                                       |....|"
+        );
+        let lines = hex_lines(&bytes[1..4], 1);
+        assert_eq!(
+            join_lines(&lines),
+            "00000001  50 4e 47                                          |PNG|"
+        );
+        assert_eq!(lines[0].num, 0);
+        assert_eq!(hex_lines(&bytes[16..], 16).len(), 1);
+        assert_eq!(hex_lines(&bytes[16..], 16).first().map(|l| l.num), Some(1));
+        assert!(hex_lines(&[], 30).is_empty());
+    }
+
+    #[test]
+    fn line_span_test() {
+        let s = "Lorem\nANCHOR: test\nipsum\nANCHOR: other\ndolor\nANCHOR_END: test\nsit";
+        let lines = take_anchored_lines(s, "test");
+        assert_eq!(lines.iter().map(|l| l.num).collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(line_span(&lines), Some(2..5));
+        assert_eq!(line_span(&take_anchored_lines(s, "missing")), None);
+        assert_eq!(line_span(&take_lines(s, 1..3)), Some(1..3));
+        assert_eq!(line_span(&take_lines(s, 5..)), Some(5..7));
+        assert_eq!(line_span(&take_lines(s, ..100)), Some(0..7));
+    }
+
+    #[test]
+    fn number_lines_test() {
+        let s =
+            "a\n  b\n\n  c\nd\ne\nf\ng\nh\n  ANCHOR: x\n  i\n  ANCHOR: y\n\n  j\n  ANCHOR_END: x";
+        let lines = shift_lines(&take_lines(s, 1..4), Shift::Auto);
+        assert_eq!(join_lines(&number_lines(&lines)), "2 | b\n3 |\n4 | c");
+        let lines = shift_lines(&take_anchored_lines(s, "x"), Shift::Right(1));
+        assert_eq!(
+            join_lines(&number_lines(&lines)),
+            "11 |    i\n13 |  \n14 |    j"
+        );
+        let lines = take_lines(s, 8..10);
+        assert_eq!(
+            join_lines(&number_lines(&lines)),
+            " 9 | h\n10 |   ANCHOR: x"
+        );
+    }
+
+    #[test]
+    fn hidden_context_test() {
+        let s = "fn main() {\n    let x = 1;\n    // ANCHOR: body\n    println!();\n\n    // ANCHOR_END: body\n}";
+        let lines = take_anchored_lines_with_hidden_context(s, "body");
+        assert_eq!(line_span(&lines), Some(3..5));
+        assert_eq!(
+            join_lines(&hide_lines(&shift_lines(&lines, Shift::Auto), "# ")),
+            "# fn main() {\n#     let x = 1;\nprintln!();\n\n# }"
+        );
+        let lines = take_lines_with_hidden_context(s, 1..2);
+        assert_eq!(line_span(&lines), Some(1..2));
+        assert_eq!(
+            join_lines(&hide_lines(&number_lines(&shift_lines(&lines, Shift::Right(1))), "~")),
+            "~fn main() {\n2 |      let x = 1;\n~    // ANCHOR: body\n~    println!();\n~\n~    // ANCHOR_END: body\n~}"
+        );
+    }
+
     #[test]
     fn take_anchored_lines_with_shift_test() {
         let s = "Lorem\nipsum\ndolor\nsit\namet";
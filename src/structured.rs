@@ -0,0 +1,337 @@
+//! Selection of subtrees from structured (JSON, TOML or YAML) files.
+
+use crate::string::{take_lines, Line};
+use anyhow::{bail, Context};
+use mdbook_preprocessor::errors::Result;
+use serde_json::value::RawValue;
+use std::{collections::HashMap, ops::Range, path::Path};
+use toml_edit::{DocumentMut, ImDocument, Item};
+
+/// The part of a structured file that was selected by a key.
+pub struct Subtree {
+    pub lines: Vec<Line>,
+    /// Whether the lines are the original text of the file (rather than a re-serialization of the
+    /// subtree, whose line numbers do not correspond to the file).
+    pub verbatim: bool,
+}
+
+/// Select the subtree of `s` with the given key, where the format is given by the extension of
+/// `path`.  The key is either a dotted path (`package.metadata`) or a JSON pointer
+/// (`/server/tls`); in either form, array elements are selected by index.  The original text of
+/// the subtree is used where it can be found, and otherwise it is re-serialized.
+pub fn select_subtree(s: &str, path: &Path, key: &str) -> Result<Subtree> {
+    let keys = parse_key(key);
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "json" => select_json(s, &keys),
+        "toml" => select_toml(s, &keys),
+        "yaml" | "yml" => select_yaml(s, &keys),
+        _ => bail!(
+            "Cannot select a key from {}, which is not a JSON, TOML or YAML file",
+            path.display()
+        ),
+    }
+}
+
+/// Split a key into its parts.
+fn parse_key(key: &str) -> Vec<String> {
+    match key.strip_prefix('/') {
+        Some(pointer) => pointer
+            .split('/')
+            .map(|k| k.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+        None => key.split('.').map(str::to_string).collect(),
+    }
+}
+
+/// The lines of the given span of `s`.  A span that starts part way through a line is given the
+/// indentation of that line, so that it lines up with the lines that follow.
+fn verbatim(s: &str, span: Range<usize>) -> Subtree {
+    let line_start = s[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let indent = s[line_start..span.start]
+        .chars()
+        .take_while(|c| c.is_whitespace())
+        .collect::<String>();
+    let first = s[..span.start].matches('\n').count();
+    let text = format!("{indent}{}", &s[span]);
+    let lines = take_lines(&text, ..)
+        .into_iter()
+        .map(|l| Line {
+            num: l.num + first,
+            ..l
+        })
+        .collect();
+    Subtree {
+        lines,
+        verbatim: true,
+    }
+}
+
+fn reserialized(text: &str) -> Subtree {
+    Subtree {
+        lines: take_lines(text.trim_matches('\n'), ..),
+        verbatim: false,
+    }
+}
+
+fn select_json(s: &str, keys: &[String]) -> Result<Subtree> {
+    let mut raw: &RawValue = serde_json::from_str(s).context("Invalid JSON")?;
+    for key in keys {
+        raw = json_child(raw, key).with_context(|| format!("No JSON value for key {key:?}"))?;
+    }
+    // The raw value borrows from `s`, which gives its position.
+    let start = raw.get().as_ptr() as usize - s.as_ptr() as usize;
+    Ok(verbatim(s, start..start + raw.get().len()))
+}
+
+fn json_child<'a>(raw: &'a RawValue, key: &str) -> Option<&'a RawValue> {
+    match raw.get().as_bytes().first() {
+        Some(b'{') => serde_json::from_str::<HashMap<String, &RawValue>>(raw.get())
+            .ok()?
+            .remove(key),
+        Some(b'[') => serde_json::from_str::<Vec<&RawValue>>(raw.get())
+            .ok()?
+            .get(key.parse::<usize>().ok()?)
+            .copied(),
+        _ => None,
+    }
+}
+
+fn toml_child<'a>(item: &'a Item, key: &str) -> Option<&'a Item> {
+    match key.parse::<usize>() {
+        Ok(index) if item.is_array() || item.is_array_of_tables() => item.get(index),
+        _ => item.get(key),
+    }
+}
+
+fn select_toml(s: &str, keys: &[String]) -> Result<Subtree> {
+    let doc = ImDocument::parse(s).context("Invalid TOML")?;
+    let mut item = doc.as_item();
+    for key in keys {
+        item = toml_child(item, key).with_context(|| format!("No TOML value for key {key:?}"))?;
+    }
+    let span = match item {
+        Item::Value(value) => value.span(),
+        Item::ArrayOfTables(array) => array.span(),
+        // The span of a table runs from its header to its last key, and so leaves out any
+        // sub-tables with their own headers.
+        Item::Table(table)
+            if table
+                .iter()
+                .all(|(_, i)| !i.is_table() && !i.is_array_of_tables()) =>
+        {
+            table.span().map(|span| {
+                let body = s[span.clone()]
+                    .find('\n')
+                    .map_or(span.end, |i| span.start + i + 1);
+                body..span.end
+            })
+        }
+        _ => None,
+    };
+    if let Some(span) = span {
+        return Ok(verbatim(s, span));
+    }
+
+    // Re-serialize the subtree, as a document of its own.
+    let mut doc = s.parse::<DocumentMut>().context("Invalid TOML")?;
+    let mut item = doc.as_item_mut();
+    for key in keys {
+        item = match key.parse::<usize>() {
+            Ok(index) if item.is_array_of_tables() => item.get_mut(index),
+            _ => item.get_mut(key.as_str()),
+        }
+        .expect("key already found");
+    }
+    let mut out = DocumentMut::new();
+    match item {
+        Item::Table(table) => {
+            for (k, v) in table.iter() {
+                out.insert(k, v.clone());
+            }
+        }
+        item => {
+            let last = keys.last().map_or("", String::as_str);
+            out.insert(last, item.clone());
+        }
+    }
+    Ok(reserialized(&out.to_string()))
+}
+
+fn select_yaml(s: &str, keys: &[String]) -> Result<Subtree> {
+    let doc: serde_norway::Value = serde_norway::from_str(s).context("Invalid YAML")?;
+    let mut value = &doc;
+    for key in keys {
+        let child = match key.parse::<usize>() {
+            Ok(index) if value.is_sequence() => value.get(index),
+            _ => value.get(key.as_str()),
+        };
+        value = child.with_context(|| format!("No YAML value for key {key:?}"))?;
+    }
+    match yaml_span(s, keys) {
+        Some(span) => Ok(verbatim(s, span)),
+        None => Ok(reserialized(
+            &serde_norway::to_string(&value).context("Could not serialize YAML")?,
+        )),
+    }
+}
+
+/// Find the span of the value with the given key in `s`, if it is held in block mappings (with
+/// one key per line).
+fn yaml_span(s: &str, keys: &[String]) -> Option<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in s.split_inclusive('\n') {
+        lines.push((offset, line.trim_end_matches(['\r', '\n'])));
+        offset += line.len();
+    }
+    let is_content = |line: &str| {
+        let t = line.trim_start();
+        !t.is_empty() && !t.starts_with('#')
+    };
+    let indent = |line: &str| line.len() - line.trim_start().len();
+
+    let mut block = 0..lines.len();
+    let mut inline = None;
+    for key in keys {
+        if inline.is_some() {
+            return None;
+        }
+        let first = block.clone().find(|&i| is_content(lines[i].1))?;
+        let level = indent(lines[first].1);
+        let found = block.clone().find_map(|i| {
+            let line = lines[i].1;
+            if !is_content(line) || indent(line) != level {
+                return None;
+            }
+            yaml_key_value(line.trim_start(), key).map(|value| (i, value))
+        });
+        let (i, value) = found?;
+        let end = (i + 1..block.end)
+            .find(|&j| is_content(lines[j].1) && indent(lines[j].1) <= level)
+            .unwrap_or(block.end);
+        let value = value.trim();
+        if value.is_empty() || value.starts_with(['|', '>']) {
+            block = i + 1..end;
+        } else if value.starts_with(['&', '*', '!']) {
+            return None;
+        } else {
+            // Drop any comment after a plain value.
+            let value = match value.find(" #") {
+                Some(end) if !value.starts_with(['"', '\'']) => value[..end].trim_end(),
+                _ => value,
+            };
+            // The value is part of `s`, which gives its position.
+            let start = value.as_ptr() as usize - s.as_ptr() as usize;
+            inline = Some(start..start + value.len());
+        }
+    }
+    if let Some(span) = inline {
+        return Some(span);
+    }
+    let last = block.clone().rev().find(|&i| is_content(lines[i].1))?;
+    let (start, _) = lines[block.start];
+    let (last_start, last_line) = lines[last];
+    Some(start..last_start + last_line.len())
+}
+
+/// If `line` holds the given key, return the text after the key's colon.
+fn yaml_key_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let rest = [
+        format!("{key}:"),
+        format!("\"{key}\":"),
+        format!("'{key}':"),
+    ]
+    .iter()
+    .find_map(|k| line.strip_prefix(k.as_str()))?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then_some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::string::join_lines;
+
+    fn select(s: &str, file: &str, key: &str) -> (String, bool, usize) {
+        let subtree = select_subtree(s, Path::new(file), key).unwrap();
+        let first = subtree.lines.first().map_or(0, |l| l.num);
+        (join_lines(&subtree.lines), subtree.verbatim, first)
+    }
+
+    #[test]
+    fn parse_key_test() {
+        assert_eq!(parse_key("a.b.0"), ["a", "b", "0"]);
+        assert_eq!(parse_key("/server/tls"), ["server", "tls"]);
+        assert_eq!(parse_key("/a~1b/c~0d"), ["a/b", "c~d"]);
+    }
+
+    #[test]
+    fn select_json_test() {
+        let s = "{\n  \"server\": {\n    \"tls\": {\n      \"cert\": \"a.pem\"\n    },\n    \"ports\": [80, 443]\n  }\n}\n";
+        assert_eq!(
+            select(s, "config.json", "/server/tls"),
+            (
+                "    {\n      \"cert\": \"a.pem\"\n    }".to_string(),
+                true,
+                2
+            )
+        );
+        assert_eq!(
+            select(s, "config.JSON", "server.ports.1"),
+            ("    443".to_string(), true, 5)
+        );
+        assert!(select_subtree(s, Path::new("config.json"), "server.nope").is_err());
+        assert!(select_subtree(s, Path::new("config.txt"), "server").is_err());
+    }
+
+    #[test]
+    fn select_toml_test() {
+        let s = "[package]\nname = \"x\"\n\n[dependencies]\nserde = { version = \"1\" }\nlog = \"0.4\"\n\n[target.'cfg(unix)'.dependencies]\nlibc = \"0.2\"\n";
+        assert_eq!(
+            select(s, "Cargo.toml", "dependencies"),
+            (
+                "serde = { version = \"1\" }\nlog = \"0.4\"".to_string(),
+                true,
+                4
+            )
+        );
+        assert_eq!(
+            select(s, "Cargo.toml", "dependencies.serde"),
+            ("{ version = \"1\" }".to_string(), true, 4)
+        );
+        // A table with sub-tables is re-serialized.
+        assert_eq!(
+            select(s, "Cargo.toml", "target"),
+            (
+                "[\"cfg(unix)\".dependencies]\nlibc = \"0.2\"".to_string(),
+                false,
+                0
+            )
+        );
+    }
+
+    #[test]
+    fn select_yaml_test() {
+        let s = "server:\n  # TLS settings\n  tls:\n    cert: a.pem\n    key: a.key\n\n  port: 8080  # default\nlist:\n- a\n- b\n";
+        assert_eq!(
+            select(s, "config.yaml", "server.tls"),
+            ("    cert: a.pem\n    key: a.key".to_string(), true, 3)
+        );
+        assert_eq!(
+            select(s, "config.yml", "/server/port"),
+            ("  8080".to_string(), true, 6)
+        );
+        // A sequence at the same indentation as its key is re-serialized.
+        assert_eq!(
+            select(s, "config.yml", "list"),
+            ("- a\n- b".to_string(), false, 0)
+        );
+        assert_eq!(
+            select(s, "config.yml", "list.1"),
+            ("b".to_string(), false, 0)
+        );
+    }
+}
@@ -0,0 +1,37 @@
+//! Helpers for tests.
+
+use std::{fs, path::Path, process::Command};
+use tempfile::TempDir;
+
+/// Create a uniquely named temporary directory holding the given files (and any directories that
+/// they need), which is removed when it is dropped.  A path that ends in `/` gives an empty
+/// directory.
+pub fn fixture(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::Builder::new()
+        .prefix("shiftinclude-test-")
+        .tempdir()
+        .unwrap();
+    for (path, contents) in files {
+        if path.ends_with('/') {
+            fs::create_dir_all(dir.path().join(path)).unwrap();
+            continue;
+        }
+        let path = dir.path().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
+/// Run git in `dir` with the given arguments, which must succeed.
+pub fn git(dir: &Path, args: &[&str]) {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {args:?} failed");
+}